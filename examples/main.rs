@@ -54,11 +54,10 @@ fn main() {
             return;
         }
     };
-    let mut read_buff: Vec<u8> = Vec::with_capacity(1024);
-    match file_handle.read(&mut read_buff, 1024, 0) {
+    let mut read_buff: Vec<u8> = Vec::new();
+    match file_handle.read_to_end(&mut read_buff) {
         Ok(bytes_read) => {
             println!("Read {} bytes", bytes_read);
-            read_buff.truncate(bytes_read as usize);
             println!("Contents: {:?}", read_buff);
         }
         Err(e) => {
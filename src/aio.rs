@@ -0,0 +1,331 @@
+//! Asynchronous file I/O on top of gfapi's glfs_*_async calls.
+//! Every call returns a future that doesn't depend on any particular
+//! runtime.  gfapi runs the operation on its own threads and calls back
+//! when it is done.  The callback stores the result in a slot shared with
+//! the future and wakes whoever polled it last.
+//! Buffers are moved into the slot for the lifetime of the operation.  If
+//! a future is dropped before the operation finishes the slot, and the
+//! buffer with it, is freed by the callback instead.
+//! Dropping a future doesn't cancel the operation.  Each GlusterFile
+//! counts the operations running on it and waits for them before closing
+//! its fd.
+use crate::glfs::*;
+use crate::gluster::{get_error, GlusterError, GlusterFile};
+use errno::errno;
+use libc::{c_int, c_void};
+
+use std::convert::TryFrom;
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+#[derive(Default)]
+struct SlotState {
+    /// Return value and errno of the finished operation
+    result: Option<(isize, i32)>,
+    waker: Option<Waker>,
+    /// Buffer gluster reads into or writes from
+    buffer: Option<Vec<u8>>,
+}
+
+struct Slot {
+    state: Mutex<SlotState>,
+    /// The count of the file the operation runs on
+    in_flight: Arc<InFlight>,
+}
+
+impl Slot {
+    fn lock(&self) -> MutexGuard<'_, SlotState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The number of async operations running on a GlusterFile
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    count: Mutex<usize>,
+    idle: Condvar,
+}
+
+impl InFlight {
+    fn lock(&self) -> MutexGuard<'_, usize> {
+        self.count.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn begin(&self) {
+        *self.lock() += 1;
+    }
+
+    fn end(&self) {
+        let mut count = self.lock();
+        *count -= 1;
+        if *count == 0 {
+            self.idle.notify_all();
+        }
+    }
+
+    /// Block until no operations are running
+    pub(crate) fn wait(&self) {
+        let mut count = self.lock();
+        while *count > 0 {
+            count = self.idle.wait(count).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+/// Called by gfapi on one of its threads once an operation finishes.  data
+/// is the reference to the slot handed over when the operation started.
+unsafe extern "C" fn io_complete(
+    _fd: *mut glfs_fd,
+    ret: isize,
+    _prestat: *mut glfs_stat,
+    _poststat: *mut glfs_stat,
+    data: *mut c_void,
+) {
+    // gfapi sets errno before calling back on failure
+    let error = errno().0;
+    let slot = Arc::from_raw(data as *const Slot);
+    let waker = {
+        let mut state = slot.lock();
+        state.result = Some((ret, error));
+        state.waker.take()
+    };
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+    // gfapi is done with the fd
+    slot.in_flight.end();
+}
+
+/// The future of an operation started with one of the *_async methods of
+/// GlusterFile.  Dropping it doesn't cancel the operation.  The file
+/// waits for the operation to finish when it is closed.
+#[must_use = "futures do nothing unless polled"]
+pub struct GlusterFuture<'a, T> {
+    /// None once the result has been returned
+    slot: Option<Arc<Slot>>,
+    /// Set if the operation couldn't be started
+    error: Option<GlusterError>,
+    /// Turn the return value and the buffer into the output
+    finish: fn(isize, Option<Vec<u8>>) -> T,
+    _file: PhantomData<&'a GlusterFile>,
+}
+
+impl<'a, T> GlusterFuture<'a, T> {
+    /// Start an operation.  submit is handed the buffer pointer, the
+    /// callback and its data and returns what the glfs_*_async call
+    /// returned.
+    fn start<F>(
+        file: &GlusterFile,
+        buffer: Option<Vec<u8>>,
+        finish: fn(isize, Option<Vec<u8>>) -> T,
+        submit: F,
+    ) -> Self
+    where
+        F: FnOnce(*mut glfs_fd, *mut u8, *mut c_void) -> c_int,
+    {
+        let slot = Arc::new(Slot {
+            state: Mutex::default(),
+            in_flight: Arc::clone(&file.in_flight),
+        });
+        let buf = {
+            let mut state = slot.lock();
+            state.buffer = buffer;
+            // The heap allocation doesn't move while the Vec sits in the
+            // slot so the pointer stays valid until the callback runs
+            match state.buffer {
+                Some(ref mut buffer) => buffer.as_mut_ptr(),
+                None => std::ptr::null_mut(),
+            }
+        };
+        // The lock isn't held here because gfapi may call back before
+        // submit returns
+        let data = Arc::into_raw(Arc::clone(&slot)) as *mut c_void;
+        file.in_flight.begin();
+        if submit(file.file_handle, buf, data) < 0 {
+            let error = get_error();
+            // The callback will never run so take its reference back
+            unsafe { drop(Arc::from_raw(data as *const Slot)) };
+            file.in_flight.end();
+            return GlusterFuture::failed(error);
+        }
+        GlusterFuture {
+            slot: Some(slot),
+            error: None,
+            finish,
+            _file: PhantomData,
+        }
+    }
+
+    fn failed(error: GlusterError) -> Self {
+        GlusterFuture {
+            slot: None,
+            error: Some(error),
+            finish: |_, _| unreachable!(),
+            _file: PhantomData,
+        }
+    }
+}
+
+impl<'a, T> Future for GlusterFuture<'a, T> {
+    type Output = Result<T, GlusterError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Some(error) = this.error.take() {
+            return Poll::Ready(Err(error));
+        }
+        let slot = this
+            .slot
+            .take()
+            .expect("GlusterFuture polled after completion");
+        let mut state = slot.lock();
+        let (ret, error) = match state.result {
+            Some(result) => result,
+            None => {
+                match state.waker {
+                    Some(ref waker) if waker.will_wake(cx.waker()) => {}
+                    _ => state.waker = Some(cx.waker().clone()),
+                }
+                drop(state);
+                this.slot = Some(slot);
+                return Poll::Pending;
+            }
+        };
+        let buffer = state.buffer.take();
+        if ret < 0 {
+            return Poll::Ready(Err(io::Error::from_raw_os_error(error).into()));
+        }
+        Poll::Ready(Ok((this.finish)(ret, buffer)))
+    }
+}
+
+fn finish_read(ret: isize, buffer: Option<Vec<u8>>) -> Vec<u8> {
+    let mut buffer = buffer.unwrap_or_default();
+    // gluster initialized the first ret bytes
+    unsafe { buffer.set_len(ret as usize) };
+    buffer
+}
+
+fn finish_write(ret: isize, _: Option<Vec<u8>>) -> usize {
+    ret as usize
+}
+
+/// Also hand the buffer back so a short write can be continued
+#[cfg(feature = "tokio")]
+fn finish_write_owned(ret: isize, buffer: Option<Vec<u8>>) -> (usize, Vec<u8>) {
+    (ret as usize, buffer.unwrap_or_default())
+}
+
+fn finish_unit(_: isize, _: Option<Vec<u8>>) {}
+
+/// Start a read.  The future doesn't borrow file.
+pub(crate) fn pread(
+    file: &GlusterFile,
+    len: usize,
+    offset: i64,
+) -> GlusterFuture<'static, Vec<u8>> {
+    GlusterFuture::start(
+        file,
+        Some(Vec::with_capacity(len)),
+        finish_read,
+        |file_handle, buf, data| unsafe {
+            glfs_pread_async(
+                file_handle,
+                buf as *mut c_void,
+                len,
+                offset,
+                0,
+                Some(io_complete),
+                data,
+            )
+        },
+    )
+}
+
+/// Start a write.  The future doesn't borrow file.
+fn pwrite<T>(
+    file: &GlusterFile,
+    buffer: Vec<u8>,
+    offset: i64,
+    finish: fn(isize, Option<Vec<u8>>) -> T,
+) -> GlusterFuture<'static, T> {
+    let count = match c_int::try_from(buffer.len()) {
+        Ok(count) => count,
+        Err(_) => {
+            return GlusterFuture::failed(GlusterError::new(format!(
+                "write of {} bytes is too large",
+                buffer.len()
+            )));
+        }
+    };
+    GlusterFuture::start(
+        file,
+        Some(buffer),
+        finish,
+        |file_handle, buf, data| unsafe {
+            glfs_pwrite_async(
+                file_handle,
+                buf as *const c_void,
+                count,
+                offset,
+                0,
+                Some(io_complete),
+                data,
+            )
+        },
+    )
+}
+
+/// Like pwrite but the future resolves to the number of bytes written and
+/// the buffer
+#[cfg(feature = "tokio")]
+pub(crate) fn pwrite_owned(
+    file: &GlusterFile,
+    buffer: Vec<u8>,
+    offset: i64,
+) -> GlusterFuture<'static, (usize, Vec<u8>)> {
+    pwrite(file, buffer, offset, finish_write_owned)
+}
+
+impl GlusterFile {
+    /// Read up to len bytes at offset.  The future resolves to a buffer
+    /// holding the bytes that were read, which is empty at the end of the
+    /// file.
+    pub fn pread_async(&self, len: usize, offset: i64) -> GlusterFuture<'_, Vec<u8>> {
+        pread(self, len, offset)
+    }
+
+    /// Write buffer at offset.  The buffer is owned by the operation until
+    /// it finishes.  The future resolves to the number of bytes written.
+    pub fn pwrite_async(&self, buffer: Vec<u8>, offset: i64) -> GlusterFuture<'_, usize> {
+        pwrite(self, buffer, offset, finish_write)
+    }
+
+    pub fn fsync_async(&self) -> GlusterFuture<'_, ()> {
+        GlusterFuture::start(self, None, finish_unit, |file_handle, _, data| unsafe {
+            glfs_fsync_async(file_handle, Some(io_complete), data)
+        })
+    }
+
+    pub fn ftruncate_async(&self, length: i64) -> GlusterFuture<'_, ()> {
+        GlusterFuture::start(self, None, finish_unit, |file_handle, _, data| unsafe {
+            glfs_ftruncate_async(file_handle, length, Some(io_complete), data)
+        })
+    }
+
+    pub fn discard_async(&self, offset: i64, len: usize) -> GlusterFuture<'_, ()> {
+        GlusterFuture::start(self, None, finish_unit, |file_handle, _, data| unsafe {
+            glfs_discard_async(file_handle, offset, len, Some(io_complete), data)
+        })
+    }
+
+    pub fn zerofill_async(&self, offset: i64, len: i64) -> GlusterFuture<'_, ()> {
+        GlusterFuture::start(self, None, finish_unit, |file_handle, _, data| unsafe {
+            glfs_zerofill_async(file_handle, offset, len, Some(io_complete), data)
+        })
+    }
+}
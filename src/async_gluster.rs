@@ -0,0 +1,396 @@
+//! Non-blocking access to the metadata calls of a Gluster handle.
+//! gfapi only has async versions of the file data calls.  Everything else
+//! blocks for a network round trip, often tens of milliseconds.  AsyncGluster
+//! runs those calls on a fixed number of worker threads it owns and hands
+//! back futures that don't depend on any particular runtime.  At most as
+//! many calls as there are workers are in flight, the rest wait in a queue.
+//! The queue is bounded so that a slow volume can't pile up work without
+//! limit.  Operations queued while it is full fail straight away.
+use crate::gluster::{DirEntry, DirEntryPlus, Gluster, GlusterError, GlusterFile};
+use libc::{mode_t, stat, statvfs, timespec, PATH_MAX};
+
+use std::ffi::OsString;
+use std::future::Future;
+use std::os::unix::ffi::OsStringExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+type Job = Box<dyn FnOnce(&Gluster) + Send>;
+
+/// Operations that can wait for a worker unless a limit is given
+const DEFAULT_QUEUE_LIMIT: usize = 1024;
+
+struct TaskState<T> {
+    result: Option<Result<T, GlusterError>>,
+    waker: Option<Waker>,
+}
+
+type Shared<T> = Arc<Mutex<TaskState<T>>>;
+
+fn lock<T>(shared: &Shared<T>) -> std::sync::MutexGuard<'_, TaskState<T>> {
+    shared.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Fills in the result of a task.  If the job is dropped without running,
+/// for example because it panicked or the pool shut down, the task fails
+/// instead of waiting forever.
+struct Completer<T> {
+    shared: Option<Shared<T>>,
+}
+
+impl<T> Completer<T> {
+    fn complete(mut self, result: Result<T, GlusterError>) {
+        if let Some(shared) = self.shared.take() {
+            finish(&shared, result);
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        if let Some(shared) = self.shared.take() {
+            finish(
+                &shared,
+                Err(GlusterError::new("gluster operation was abandoned".into())),
+            );
+        }
+    }
+}
+
+fn finish<T>(shared: &Shared<T>, result: Result<T, GlusterError>) {
+    let waker = {
+        let mut state = lock(shared);
+        state.result = Some(result);
+        state.waker.take()
+    };
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+/// The result of an operation queued on an AsyncGluster.  Dropping it
+/// doesn't cancel the operation.
+#[must_use = "futures do nothing unless polled"]
+pub struct GlusterTask<T> {
+    shared: Shared<T>,
+}
+
+impl<T> Future for GlusterTask<T> {
+    type Output = Result<T, GlusterError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = lock(&self.shared);
+        if let Some(result) = state.result.take() {
+            return Poll::Ready(result);
+        }
+        match state.waker {
+            Some(ref waker) if waker.will_wake(cx.waker()) => {}
+            _ => state.waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+}
+
+/// A Gluster handle whose calls return futures
+pub struct AsyncGluster {
+    gluster: Arc<Gluster>,
+    // None once the pool is shutting down
+    sender: Mutex<Option<SyncSender<Job>>>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+fn work(gluster: Arc<Gluster>, receiver: Arc<Mutex<Receiver<Job>>>) {
+    loop {
+        let job = {
+            let receiver = receiver.lock().unwrap_or_else(|e| e.into_inner());
+            match receiver.recv() {
+                Ok(job) => job,
+                // Every sender is gone so the pool is shutting down
+                Err(_) => return,
+            }
+        };
+        // A panicking job fails its own task through the Completer but
+        // shouldn't take a worker down with it
+        if panic::catch_unwind(AssertUnwindSafe(|| job(&gluster))).is_err() {
+            error!("gluster worker job panicked");
+        }
+    }
+}
+
+impl AsyncGluster {
+    /// Run the calls of gluster on a pool of workers threads.  Up to 1024
+    /// operations can wait for a worker.
+    pub fn new(gluster: Arc<Gluster>, workers: usize) -> Result<AsyncGluster, GlusterError> {
+        AsyncGluster::with_queue_limit(gluster, workers, DEFAULT_QUEUE_LIMIT)
+    }
+
+    /// Like new but at most queue_limit operations wait for a worker.
+    /// Operations queued beyond that fail.
+    pub fn with_queue_limit(
+        gluster: Arc<Gluster>,
+        workers: usize,
+        queue_limit: usize,
+    ) -> Result<AsyncGluster, GlusterError> {
+        let (sender, receiver) = sync_channel::<Job>(queue_limit);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let mut pool = AsyncGluster {
+            gluster: Arc::clone(&gluster),
+            sender: Mutex::new(Some(sender)),
+            workers: Vec::new(),
+        };
+        for i in 0..workers.max(1) {
+            let gluster = Arc::clone(&gluster);
+            let receiver = Arc::clone(&receiver);
+            // If spawning fails the workers started so far are shut down
+            // when pool is dropped
+            let handle = thread::Builder::new()
+                .name(format!("gfapi-worker-{}", i))
+                .spawn(move || work(gluster, receiver))?;
+            pool.workers.push(handle);
+        }
+        Ok(pool)
+    }
+
+    /// The underlying handle for calls that should block
+    pub fn gluster(&self) -> &Arc<Gluster> {
+        &self.gluster
+    }
+
+    /// Number of worker threads
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Run the operations already queued and wait for the workers to exit.
+    /// This blocks so it shouldn't be called from an async task.
+    pub fn shutdown(mut self) {
+        self.sender.lock().unwrap_or_else(|e| e.into_inner()).take();
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                error!("gluster worker thread panicked");
+            }
+        }
+    }
+
+    /// Queue op to run on a worker
+    pub fn run<T, F>(&self, op: F) -> GlusterTask<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Gluster) -> Result<T, GlusterError> + Send + 'static,
+    {
+        let shared = Arc::new(Mutex::new(TaskState {
+            result: None,
+            waker: None,
+        }));
+        let completer = Completer {
+            shared: Some(Arc::clone(&shared)),
+        };
+        let job: Job = Box::new(move |gluster| completer.complete(op(gluster)));
+        let sender = self.sender.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(ref sender) = *sender {
+            // If the send fails the job is dropped which fails the task.
+            // Waiting for room would block the caller's executor.
+            if let Err(TrySendError::Full(job)) = sender.try_send(job) {
+                drop(job);
+                // Nobody can have polled the task yet so this replaces the
+                // result the dropped job left
+                finish(
+                    &shared,
+                    Err(GlusterError::new("gluster operation queue is full".into())),
+                );
+            }
+        }
+        GlusterTask { shared }
+    }
+
+    pub fn stat(&self, path: &Path) -> GlusterTask<stat> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.stat(&path))
+    }
+
+    pub fn lstat(&self, path: &Path) -> GlusterTask<stat> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.lsstat(&path))
+    }
+
+    pub fn statvfs(&self, path: &Path) -> GlusterTask<statvfs> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.statvfs(&path))
+    }
+
+    pub fn exists(&self, path: &Path) -> GlusterTask<bool> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.exists(&path))
+    }
+
+    pub fn access(&self, path: &Path, mode: i32) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.access(&path, mode))
+    }
+
+    pub fn open(&self, path: &Path, flags: i32) -> GlusterTask<GlusterFile> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.open(&path, flags))
+    }
+
+    pub fn create(&self, path: &Path, flags: i32, mode: mode_t) -> GlusterTask<GlusterFile> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.create(&path, flags, mode))
+    }
+
+    pub fn truncate(&self, path: &Path, length: i64) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.truncate(&path, length))
+    }
+
+    pub fn mkdir(&self, path: &Path, mode: mode_t) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.mkdir(&path, mode))
+    }
+
+    pub fn rmdir(&self, path: &Path) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.rmdir(&path))
+    }
+
+    pub fn remove_dir_all(&self, path: &Path) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.remove_dir_all(&path))
+    }
+
+    pub fn unlink(&self, path: &Path) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.unlink(&path))
+    }
+
+    pub fn rename(&self, oldpath: &Path, newpath: &Path) -> GlusterTask<()> {
+        let oldpath = oldpath.to_path_buf();
+        let newpath = newpath.to_path_buf();
+        self.run(move |gluster| gluster.rename(&oldpath, &newpath))
+    }
+
+    pub fn link(&self, oldpath: &Path, newpath: &Path) -> GlusterTask<()> {
+        let oldpath = oldpath.to_path_buf();
+        let newpath = newpath.to_path_buf();
+        self.run(move |gluster| gluster.link(&oldpath, &newpath))
+    }
+
+    pub fn symlink(&self, oldpath: &Path, newpath: &Path) -> GlusterTask<()> {
+        let oldpath = oldpath.to_path_buf();
+        let newpath = newpath.to_path_buf();
+        self.run(move |gluster| gluster.symlink(&oldpath, &newpath))
+    }
+
+    /// Return the target of the symlink at path
+    pub fn readlink(&self, path: &Path) -> GlusterTask<PathBuf> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| {
+            let mut buf = vec![0; PATH_MAX as usize + 1];
+            gluster.readlink(&path, &mut buf)?;
+            let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+            buf.truncate(len);
+            Ok(PathBuf::from(OsString::from_vec(buf)))
+        })
+    }
+
+    /// Open the directory at path and read all of its entries
+    pub fn read_dir(&self, path: &Path) -> GlusterTask<Vec<DirEntry>> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.opendir(&path)?.collect())
+    }
+
+    /// Like read_dir but every entry comes with its stat
+    pub fn read_dir_plus(&self, path: &Path) -> GlusterTask<Vec<DirEntryPlus>> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.opendir_plus(&path)?.collect())
+    }
+
+    pub fn getxattr(&self, path: &Path, name: &str) -> GlusterTask<String> {
+        let path = path.to_path_buf();
+        let name = name.to_string();
+        self.run(move |gluster| gluster.getxattr(&path, &name))
+    }
+
+    pub fn lgetxattr(&self, path: &Path, name: &str) -> GlusterTask<String> {
+        let path = path.to_path_buf();
+        let name = name.to_string();
+        self.run(move |gluster| gluster.lgetxattr(&path, &name))
+    }
+
+    pub fn listxattr(&self, path: &Path) -> GlusterTask<String> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.listxattr(&path))
+    }
+
+    pub fn llistxattr(&self, path: &Path) -> GlusterTask<String> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.llistxattr(&path))
+    }
+
+    pub fn setxattr(&self, path: &Path, name: &str, value: &[u8], flags: i32) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        let name = name.to_string();
+        let value = value.to_vec();
+        self.run(move |gluster| gluster.setxattr(&path, &name, &value, flags))
+    }
+
+    pub fn lsetxattr(&self, path: &Path, name: &str, value: &[u8], flags: i32) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        let name = name.to_string();
+        let value = value.to_vec();
+        self.run(move |gluster| gluster.lsetxattr(&name, &value, &path, flags))
+    }
+
+    pub fn removexattr(&self, path: &Path, name: &str) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        let name = name.to_string();
+        self.run(move |gluster| gluster.removexattr(&path, &name))
+    }
+
+    pub fn lremovexattr(&self, path: &Path, name: &str) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        let name = name.to_string();
+        self.run(move |gluster| gluster.lremovexattr(&path, &name))
+    }
+
+    pub fn utimens(&self, path: &Path, times: &[timespec; 2]) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        let times = *times;
+        self.run(move |gluster| gluster.utimens(&path, &times))
+    }
+
+    pub fn lutimens(&self, path: &Path, times: &[timespec; 2]) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        let times = *times;
+        self.run(move |gluster| gluster.lutimens(&path, &times))
+    }
+
+    pub fn chmod(&self, path: &Path, mode: mode_t) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.chmod(&path, mode))
+    }
+
+    pub fn chown(&self, path: &Path, uid: u32, gid: u32) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.chown(&path, uid, gid))
+    }
+
+    pub fn lchown(&self, path: &Path, uid: u32, gid: u32) -> GlusterTask<()> {
+        let path = path.to_path_buf();
+        self.run(move |gluster| gluster.lchown(&path, uid, gid))
+    }
+}
+
+impl Drop for AsyncGluster {
+    /// The workers are detached rather than joined so that dropping this
+    /// inside an async task doesn't block the executor.  Operations already
+    /// queued still run before they exit.  Use shutdown to wait for them.
+    fn drop(&mut self) {
+        self.sender.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+}
@@ -0,0 +1,131 @@
+//! Copy files inside a volume.
+//! glfs_copy_file_range asks the bricks to copy the data themselves so it
+//! never crosses the network to the client.  Volumes or servers that can't
+//! do that get a plain read and write loop instead.
+use crate::glfs::*;
+use crate::gluster::{get_error, Gluster, GlusterError, GlusterFile};
+use crate::io::{to_offset, GlusterFileExt};
+use libc::{stat, timespec, ENOENT, EXDEV, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY};
+
+use std::path::Path;
+use std::ptr;
+
+/// How much a single copy_file_range or read asks for
+const COPY_CHUNK: usize = 1024 * 1024;
+
+impl GlusterFile {
+    /// Copy len bytes at src_off of this file to dst_off of dst on the
+    /// server side.  Neither file offset is used or moved.  Returns the
+    /// number of bytes copied which can be less than len, and 0 at the end
+    /// of this file.
+    pub fn copy_range_to(
+        &self,
+        dst: &GlusterFile,
+        src_off: u64,
+        dst_off: u64,
+        len: usize,
+    ) -> Result<usize, GlusterError> {
+        let mut off_in = to_offset(src_off)?;
+        let mut off_out = to_offset(dst_off)?;
+        unsafe {
+            let copied = glfs_copy_file_range(
+                self.file_handle,
+                &mut off_in,
+                dst.file_handle,
+                &mut off_out,
+                len,
+                0,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            if copied < 0 {
+                return Err(get_error());
+            }
+            Ok(copied as usize)
+        }
+    }
+}
+
+/// Copy with a read and write loop starting at offset.  Returns the offset
+/// the source ended at.
+fn copy_by_reading(
+    src: &GlusterFile,
+    dst: &GlusterFile,
+    mut offset: u64,
+) -> Result<u64, GlusterError> {
+    let mut buffer = vec![0; COPY_CHUNK];
+    loop {
+        let read = src.read_at(&mut buffer, offset)?;
+        if read == 0 {
+            return Ok(offset);
+        }
+        dst.write_all_at(&buffer[..read], offset)?;
+        offset += read as u64;
+    }
+}
+
+pub(crate) fn times_of(stat: &stat) -> [timespec; 2] {
+    [
+        timespec {
+            tv_sec: stat.st_atime,
+            tv_nsec: stat.st_atime_nsec,
+        },
+        timespec {
+            tv_sec: stat.st_mtime,
+            tv_nsec: stat.st_mtime_nsec,
+        },
+    ]
+}
+
+impl Gluster {
+    /// Copy the file at src to dst, replacing dst if it exists.  The data
+    /// is copied on the server side when the volume supports it.  dst gets
+    /// the permission bits and access and modification times of src.
+    /// Returns the number of bytes copied.  Copying a file onto itself, a
+    /// hard link of itself or a symlink to itself is an error.
+    pub fn copy_file(&self, src: &Path, dst: &Path) -> Result<u64, GlusterError> {
+        let src_file = self.open(src, O_RDONLY)?;
+        let src_stat = src_file.fstat()?;
+        // Truncating dst would otherwise empty src
+        match self.stat(dst) {
+            Ok(dst_stat)
+                if dst_stat.st_dev == src_stat.st_dev && dst_stat.st_ino == src_stat.st_ino =>
+            {
+                return Err(GlusterError::new(format!(
+                    "{} and {} are the same file",
+                    src.display(),
+                    dst.display()
+                )));
+            }
+            Ok(_) => {}
+            Err(ref e) if e.is_errno(ENOENT) => {}
+            Err(e) => return Err(e),
+        }
+        let mode = src_stat.st_mode & 0o7777;
+        let dst_file = self.create(dst, O_CREAT | O_WRONLY | O_TRUNC, mode)?;
+
+        let mut offset = 0;
+        let copied = loop {
+            match src_file.copy_range_to(&dst_file, offset, offset, COPY_CHUNK) {
+                Ok(0) => break offset,
+                Ok(copied) => offset += copied as u64,
+                // EXDEV means the bricks can't copy between these files
+                Err(ref e) if e.is_unsupported() || e.is_errno(EXDEV) => {
+                    debug!(
+                        "copy_file_range of {} failed with {}, copying through the client",
+                        src.display(),
+                        e
+                    );
+                    break copy_by_reading(&src_file, &dst_file, offset)?;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        // The mode given to create is masked by the umask
+        dst_file.fchmod(mode)?;
+        dst_file.futimens(&times_of(&src_stat))?;
+        Ok(copied)
+    }
+}
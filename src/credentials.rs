@@ -0,0 +1,203 @@
+//! Per thread filesystem credentials.  gfapi sends the uid, gid, groups
+//! and lease id of the calling thread with every operation so that the
+//! bricks enforce permissions for that identity instead of the process.
+use crate::glfs::*;
+use crate::gluster::{get_error, GlusterError};
+use libc::{c_char, gid_t, uid_t};
+
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::ptr;
+
+/// Length in bytes of a lease id
+pub const GLAPI_LEASE_ID_SIZE: usize = 16;
+
+/// The identity gfapi uses for operations issued from a thread
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Credentials {
+    pub uid: uid_t,
+    pub gid: gid_t,
+    pub groups: Vec<gid_t>,
+    pub lease_id: Option<[u8; GLAPI_LEASE_ID_SIZE]>,
+}
+
+impl Credentials {
+    pub fn new(uid: uid_t, gid: gid_t) -> Credentials {
+        Credentials {
+            uid,
+            gid,
+            groups: Vec::new(),
+            lease_id: None,
+        }
+    }
+
+    /// Supplementary groups
+    pub fn groups(mut self, groups: &[gid_t]) -> Credentials {
+        self.groups = groups.to_vec();
+        self
+    }
+
+    pub fn lease_id(mut self, lease_id: [u8; GLAPI_LEASE_ID_SIZE]) -> Credentials {
+        self.lease_id = Some(lease_id);
+        self
+    }
+
+    /// The identity gfapi uses when a thread hasn't set one: the effective
+    /// uid and gid and the supplementary groups of the process.
+    fn process_default() -> Credentials {
+        unsafe { Credentials::new(libc::geteuid(), libc::getegid()).groups(&process_groups()) }
+    }
+}
+
+fn process_groups() -> Vec<gid_t> {
+    loop {
+        // Asking for the count can't fail
+        let count = unsafe { libc::getgroups(0, ptr::null_mut()) };
+        let mut groups: Vec<gid_t> = vec![0; count.max(0) as usize];
+        let ret_code = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+        if ret_code >= 0 {
+            groups.truncate(ret_code as usize);
+            return groups;
+        }
+        // The groups changed in between, ask again
+    }
+}
+
+thread_local! {
+    // gfapi has no getters so the identity set on this thread is tracked here
+    static CURRENT: RefCell<Option<Credentials>> = const { RefCell::new(None) };
+}
+
+/// The credentials currently applied to this thread
+pub fn current() -> Credentials {
+    CURRENT.with(|c| {
+        c.borrow()
+            .clone()
+            .unwrap_or_else(Credentials::process_default)
+    })
+}
+
+fn update_current<F: FnOnce(&mut Credentials)>(f: F) {
+    CURRENT.with(|c| {
+        let mut c = c.borrow_mut();
+        let mut creds = c.take().unwrap_or_else(Credentials::process_default);
+        f(&mut creds);
+        *c = Some(creds);
+    })
+}
+
+/// Set the uid used for filesystem operations on this thread
+pub fn setfsuid(uid: uid_t) -> Result<(), GlusterError> {
+    unsafe {
+        let ret_code = glfs_setfsuid(uid);
+        if ret_code < 0 {
+            return Err(get_error());
+        }
+    }
+    update_current(|c| c.uid = uid);
+    Ok(())
+}
+
+/// Set the gid used for filesystem operations on this thread
+pub fn setfsgid(gid: gid_t) -> Result<(), GlusterError> {
+    unsafe {
+        let ret_code = glfs_setfsgid(gid);
+        if ret_code < 0 {
+            return Err(get_error());
+        }
+    }
+    update_current(|c| c.gid = gid);
+    Ok(())
+}
+
+/// Set the supplementary groups used for filesystem operations on this thread
+pub fn setfsgroups(groups: &[gid_t]) -> Result<(), GlusterError> {
+    unsafe {
+        let ret_code = glfs_setfsgroups(groups.len(), groups.as_ptr() as _);
+        if ret_code < 0 {
+            return Err(get_error());
+        }
+    }
+    update_current(|c| c.groups = groups.to_vec());
+    Ok(())
+}
+
+/// Set the lease id used for filesystem operations on this thread.
+/// None clears it.
+pub fn setfsleaseid(lease_id: Option<[u8; GLAPI_LEASE_ID_SIZE]>) -> Result<(), GlusterError> {
+    let mut id = lease_id;
+    unsafe {
+        let id_ptr = match id {
+            Some(ref mut id) => id.as_mut_ptr() as *mut c_char,
+            None => ptr::null_mut(),
+        };
+        let ret_code = glfs_setfsleaseid(id_ptr);
+        if ret_code < 0 {
+            return Err(get_error());
+        }
+    }
+    update_current(|c| c.lease_id = lease_id);
+    Ok(())
+}
+
+fn apply(creds: &Credentials) -> Result<(), GlusterError> {
+    setfsuid(creds.uid)?;
+    setfsgid(creds.gid)?;
+    setfsgroups(&creds.groups)?;
+    setfsleaseid(creds.lease_id)?;
+    Ok(())
+}
+
+/// Put the thread back to using the identity of the process.  gfapi can't
+/// forget what was set on a thread so the process identity as of now is
+/// set instead.
+fn reset() -> Result<(), GlusterError> {
+    apply(&Credentials::process_default())?;
+    CURRENT.with(|c| *c.borrow_mut() = None);
+    Ok(())
+}
+
+/// Put back credentials saved from CURRENT.  None means nothing was set.
+fn restore(previous: &Option<Credentials>) -> Result<(), GlusterError> {
+    match *previous {
+        Some(ref creds) => apply(creds),
+        None => reset(),
+    }
+}
+
+/// Applies credentials to the current thread and restores the previous
+/// ones when dropped.  Guards can be nested.  When the outermost guard is
+/// dropped the thread goes back to the identity of the process, including
+/// its supplementary groups.  Because the credentials belong to a thread
+/// the guard can't be sent to another thread.
+#[derive(Debug)]
+pub struct CredentialGuard {
+    /// None if the thread had no credentials set
+    previous: Option<Credentials>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl CredentialGuard {
+    pub fn new(creds: &Credentials) -> Result<CredentialGuard, GlusterError> {
+        let previous = CURRENT.with(|c| c.borrow().clone());
+        if let Err(e) = apply(creds) {
+            // Don't leave the thread with half of the new identity
+            if let Err(restore_error) = restore(&previous) {
+                error!("Unable to restore credentials: {:?}", restore_error);
+            }
+            return Err(e);
+        }
+        Ok(CredentialGuard {
+            previous,
+            _not_send: PhantomData,
+        })
+    }
+}
+
+impl Drop for CredentialGuard {
+    fn drop(&mut self) {
+        if let Err(e) = restore(&self.previous) {
+            error!("Unable to restore credentials: {:?}", e);
+        }
+    }
+}
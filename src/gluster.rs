@@ -1,20 +1,30 @@
 use errno::{errno, Errno};
+use crate::aio::InFlight;
 use crate::glfs::*;
+use crate::io::{iov_count, to_offset};
+use crate::logging::forward_logging;
+use crate::upcall::UpcallRegistration;
 use libc::{
-    c_uchar, c_void, dev_t, dirent, flock, ino_t, mode_t, stat, statvfs, timespec, DT_DIR, ENOENT,
-    LOCK_EX, LOCK_SH, LOCK_UN,
+    c_char, c_uchar, c_void, dev_t, dirent, flock, ino_t, mode_t, stat, statvfs, timespec, DT_DIR,
+    EACCES, EINTR, EINVAL, ELOOP, ENOENT, ENOSYS, EOPNOTSUPP, EPERM, LOCK_EX, LOCK_SH, LOCK_UN,
+    O_NOFOLLOW, PATH_MAX, S_IFDIR, S_IFLNK, S_IFMT,
 };
 use uuid::Uuid;
 
 use std::error::Error as err;
-use std::ffi::{CStr, CString, IntoStringError, NulError};
+use std::ffi::{CStr, CString, IntoStringError, NulError, OsStr, OsString};
 use std::fmt;
-use std::io::Error;
-use std::mem::zeroed;
-use std::os::unix::ffi::OsStrExt;
-use std::path::{Path, PathBuf};
+use std::fs;
+use std::io::{Error, IoSlice, IoSliceMut, Write};
+use std::mem::{zeroed, MaybeUninit};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Component, Path, PathBuf};
 use std::ptr;
+use std::slice;
 use std::string::FromUtf8Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 /// Custom error handling for the library
 #[derive(Debug)]
@@ -29,7 +39,11 @@ pub enum GlusterError {
 
 impl fmt::Display for GlusterError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str(self.description())
+        match *self {
+            // description() of an OS error only names its kind
+            GlusterError::IoError(ref e) => e.fmt(f),
+            _ => f.write_str(self.description()),
+        }
     }
 }
 
@@ -57,7 +71,7 @@ impl err for GlusterError {
 }
 impl GlusterError {
     /// Create a new GlusterError with a String message
-    fn new(err: String) -> GlusterError {
+    pub(crate) fn new(err: String) -> GlusterError {
         GlusterError::Error(err)
     }
 
@@ -68,10 +82,28 @@ impl GlusterError {
             GlusterError::Error(ref err) => err.to_string(),
             GlusterError::FromUtf8Error(ref err) => err.utf8_error().to_string(),
             GlusterError::IntoStringError(ref err) => err.description().to_string(),
-            GlusterError::IoError(ref err) => err.description().to_string(),
+            GlusterError::IoError(ref err) => err.to_string(),
             GlusterError::NulError(ref err) => err.description().to_string(),
         }
     }
+
+    /// True if this error was caused by the given errno, for example
+    /// libc::ENOTCONN
+    pub fn is_errno(&self, code: i32) -> bool {
+        match *self {
+            GlusterError::IoError(ref err) => err.raw_os_error() == Some(code),
+            _ => false,
+        }
+    }
+
+    /// True if the volume or a translator on the way doesn't implement
+    /// the call.  Some report that as EINVAL rather than ENOSYS or
+    /// EOPNOTSUPP.
+    pub(crate) fn is_unsupported(&self) -> bool {
+        [ENOSYS, EOPNOTSUPP, EINVAL]
+            .iter()
+            .any(|code| self.is_errno(*code))
+    }
 }
 
 impl From<uuid::BytesError> for GlusterError {
@@ -104,15 +136,48 @@ impl From<Error> for GlusterError {
     }
 }
 
+impl From<GlusterError> for Error {
+    fn from(err: GlusterError) -> Error {
+        match err {
+            GlusterError::IoError(err) => err,
+            err => Error::other(err),
+        }
+    }
+}
+
 //impl From<uuid::parser::ParseError> for GlusterError {
 //fn from(err: uuid::parser::ParseError) -> GlusterError {
 //GlusterError::ParseError(err)
 //}
 //}
 
-fn get_error() -> String {
-    let error = errno();
-    format!("{}", error)
+/// The error gfapi left in errno.  The errno is kept so callers can tell
+/// errors apart with GlusterError::is_errno.
+pub(crate) fn get_error() -> GlusterError {
+    GlusterError::IoError(Error::from_raw_os_error(errno().0))
+}
+
+/// Ask for the size with a null buffer first, then fetch.  fetch returns
+/// what the underlying call returned, error turns a failure into the error.
+pub(crate) fn sized_fetch<F>(
+    mut fetch: F,
+    error: fn() -> GlusterError,
+) -> Result<Vec<u8>, GlusterError>
+where
+    F: FnMut(*mut c_void, usize) -> isize,
+{
+    let size = fetch(ptr::null_mut(), 0);
+    if size < 0 {
+        return Err(error());
+    }
+    let mut buf: Vec<u8> = Vec::with_capacity(size as usize);
+    let len = fetch(buf.as_mut_ptr() as *mut c_void, buf.capacity());
+    if len < 0 {
+        return Err(error());
+    }
+    // The call filled len bytes
+    unsafe { buf.set_len(len as usize) };
+    Ok(buf)
 }
 
 /// Apply or remove an advisory lock on the open file.
@@ -138,7 +203,7 @@ impl Into<i32> for PosixLockCmd {
 }
 
 #[repr(i32)]
-#[derive(PartialEq, Debug, Hash)]
+#[derive(Clone, Copy, PartialEq, Debug, Hash)]
 ///  None to Trace correspond to the equivalent gluster log levels
 pub enum GlusterLogLevel {
     None = 0,
@@ -153,24 +218,53 @@ pub enum GlusterLogLevel {
     Trace,
 }
 
-// pub type glfs_io_cbk = ::std::option::Option<extern "C" fn(fd: *mut glfs_fd_t,
-// ret: ssize_t,
-// data: *mut c_void)
-// -> ()>;pub type glfs_io_cbk = ::std::option::Option<extern "C" fn(fd: *mut glfs_fd_t,
-// ret: ssize_t,
-// data: *mut c_void)
-// -> ()>;
-//
+/// Keys understood by glfs_sysrq
+#[derive(Clone, Copy, PartialEq, Debug, Hash)]
+pub enum SysRqKey {
+    /// Log the list of supported keys
+    Help,
+    /// Write a statedump of the client to the statedump path
+    Statedump,
+}
+
+impl From<SysRqKey> for ::libc::c_char {
+    fn from(key: SysRqKey) -> ::libc::c_char {
+        match key {
+            SysRqKey::Help => b'h' as ::libc::c_char,
+            SysRqKey::Statedump => b's' as ::libc::c_char,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Gluster {
-    cluster_handle: *mut glfs,
+    pub(crate) cluster_handle: *mut glfs,
+    /// Set when the volume is mounted from a subdirectory.  All paths
+    /// are resolved beneath it.
+    root: Option<PathBuf>,
+    /// Handed to gluster as the upcall callback data.  Boxed so it stays
+    /// put and freed only after glfs_fini has stopped the callbacks.
+    pub(crate) upcalls: Box<UpcallRegistration>,
 }
 
+/// How much read_to_end asks gluster for at a time
+const READ_CHUNK: usize = 128 * 1024;
+
 /// Gluster file descriptor
 #[derive(Debug)]
 pub struct GlusterFile {
-    file_handle: *mut glfs_fd,
+    pub(crate) file_handle: *mut glfs_fd,
+    /// Async operations gfapi is still running on the fd
+    pub(crate) in_flight: Arc<InFlight>,
+}
+
+impl GlusterFile {
+    pub(crate) fn from_raw(file_handle: *mut glfs_fd) -> GlusterFile {
+        GlusterFile {
+            file_handle,
+            in_flight: Arc::default(),
+        }
+    }
 }
 
 impl Drop for GlusterFile {
@@ -179,15 +273,23 @@ impl Drop for GlusterFile {
             // No cleanup needed
             return;
         }
+        // Dropping the future of an async operation doesn't stop it so
+        // the fd has to stay open until gfapi is done with it
+        self.in_flight.wait();
         unsafe {
             let retcode = glfs_close(self.file_handle);
             if retcode < 0 {
-                error!("{:?}", GlusterError::new(get_error()));
+                error!("{:?}", get_error());
             }
         }
     }
 }
 
+// gfapi serializes access to an fd internally.  Sharing one between
+// threads is fine as long as they use positional reads and writes.
+unsafe impl Send for GlusterFile {}
+unsafe impl Sync for GlusterFile {}
+
 // As far as I can tell the cluster handle to gluster is thread safe
 unsafe impl Send for Gluster {}
 unsafe impl Sync for Gluster {}
@@ -201,7 +303,7 @@ impl Drop for Gluster {
         unsafe {
             let retcode = glfs_fini(self.cluster_handle);
             if retcode < 0 {
-                error!("{:?}", GlusterError::new(get_error()));
+                error!("{:?}", get_error());
             }
         }
     }
@@ -223,7 +325,7 @@ impl Drop for GlusterDirectoryPlus {
         unsafe {
             let retcode = glfs_closedir(self.dir_handle);
             if retcode < 0 {
-                error!("{:?}", GlusterError::new(get_error()));
+                error!("{:?}", get_error());
             }
         }
     }
@@ -246,7 +348,7 @@ impl Iterator for GlusterDirectoryPlus {
             let ret_code =
                 glfs_readdirplus_r(self.dir_handle, &mut stat_buf, &mut dirent, &mut next_entry);
             if ret_code < 0 {
-                return Some(Err(GlusterError::new(get_error())));
+                return Some(Err(get_error()));
             }
             if dirent.d_ino == 0 {
                 // End of stream reached
@@ -277,7 +379,7 @@ impl Drop for GlusterDirectory {
         unsafe {
             let retcode = glfs_closedir(self.dir_handle);
             if retcode < 0 {
-                error!("{:?}", GlusterError::new(get_error()));
+                error!("{:?}", get_error());
             }
         }
     }
@@ -298,7 +400,7 @@ impl Iterator for GlusterDirectory {
         unsafe {
             let ret_code = glfs_readdir_r(self.dir_handle, &mut dirent, &mut next_entry);
             if ret_code < 0 {
-                return Some(Err(GlusterError::new(get_error())));
+                return Some(Err(get_error()));
             }
             if dirent.d_ino == 0 {
                 // End of stream reached
@@ -314,40 +416,626 @@ impl Iterator for GlusterDirectory {
     }
 }
 
-impl Gluster {
-    /// Connect to a GlusterFS cluster and return a connection handle glfs_t
-    /// port is usually 24007 but may differ depending on how the service was configured
-    pub fn connect(volume_name: &str, server: &str, port: u16) -> Result<Gluster, GlusterError> {
-        let vol_name = CString::new(volume_name)?;
-        let vol_transport = CString::new("tcp")?;
-        let vol_host = CString::new(server)?;
+/// A volfile server that glusterd can be reached on.  The transport
+/// is chosen per server.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum VolfileServer {
+    /// Connect over tcp.  port is usually 24007
+    Tcp { host: String, port: u16 },
+    /// Connect over rdma.  port is usually 24008
+    Rdma { host: String, port: u16 },
+    /// Connect over a local unix domain socket such as
+    /// /var/run/glusterd.socket
+    Unix { path: PathBuf },
+}
+
+impl VolfileServer {
+    pub fn tcp(host: &str, port: u16) -> VolfileServer {
+        VolfileServer::Tcp {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    pub fn rdma(host: &str, port: u16) -> VolfileServer {
+        VolfileServer::Rdma {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    pub fn unix(path: &Path) -> VolfileServer {
+        VolfileServer::Unix {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// Returns the transport, host and port arguments for glfs_set_volfile_server
+    fn to_args(&self) -> Result<(CString, CString, i32), GlusterError> {
+        match *self {
+            VolfileServer::Tcp { ref host, port } => {
+                Ok((CString::new("tcp")?, CString::new(host.as_str())?, port as i32))
+            }
+            VolfileServer::Rdma { ref host, port } => {
+                Ok((CString::new("rdma")?, CString::new(host.as_str())?, port as i32))
+            }
+            VolfileServer::Unix { ref path } => Ok((
+                CString::new("unix")?,
+                CString::new(path.as_os_str().as_bytes())?,
+                0,
+            )),
+        }
+    }
+}
+
+/// Client side performance translators
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PerformanceXlator {
+    IoCache,
+    IoThreads,
+    MdCache,
+    NlCache,
+    OpenBehind,
+    QuickRead,
+    ReadAhead,
+    ReaddirAhead,
+    WriteBehind,
+}
+
+impl PerformanceXlator {
+    fn name(self) -> &'static str {
+        match self {
+            PerformanceXlator::IoCache => "io-cache",
+            PerformanceXlator::IoThreads => "io-threads",
+            PerformanceXlator::MdCache => "md-cache",
+            PerformanceXlator::NlCache => "nl-cache",
+            PerformanceXlator::OpenBehind => "open-behind",
+            PerformanceXlator::QuickRead => "quick-read",
+            PerformanceXlator::ReadAhead => "read-ahead",
+            PerformanceXlator::ReaddirAhead => "readdir-ahead",
+            PerformanceXlator::WriteBehind => "write-behind",
+        }
+    }
+}
+
+/// Options that tune the client graph.  These only change this client and
+/// leave the volume options of the cluster alone.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum XlatorOption {
+    /// Turn a performance translator on or off.  A translator that is off
+    /// passes every call straight through to the next translator.
+    Performance(PerformanceXlator, bool),
+    /// Name of the replica subvolume that reads should be served from
+    ReadSubvolume(String),
+    /// Index of the replica subvolume that reads should be served from
+    ReadSubvolumeIndex(i32),
+    /// Turn ssl on or off for the connections to the bricks
+    /// (transport.socket.ssl-enabled)
+    TransportSocketSsl(bool),
+    /// Set any option.  The xlator name may be a glob such as
+    /// "*-write-behind" and is matched against the names in the volfile.
+    Raw {
+        xlator: String,
+        key: String,
+        value: String,
+    },
+}
+
+impl XlatorOption {
+    /// Returns the xlator, key and value arguments for glfs_set_xlator_option
+    fn to_args(&self) -> Result<(CString, CString, CString), GlusterError> {
+        let on_off = |b: bool| if b { "on" } else { "off" };
+        let (xlator, key, value) = match *self {
+            XlatorOption::Performance(xlator, enabled) => (
+                format!("*-{}", xlator.name()),
+                "pass-through".to_string(),
+                on_off(!enabled).to_string(),
+            ),
+            XlatorOption::ReadSubvolume(ref subvolume) => (
+                "*-replicate-*".to_string(),
+                "read-subvolume".to_string(),
+                subvolume.clone(),
+            ),
+            XlatorOption::ReadSubvolumeIndex(index) => (
+                "*-replicate-*".to_string(),
+                "read-subvolume-index".to_string(),
+                index.to_string(),
+            ),
+            XlatorOption::TransportSocketSsl(enabled) => (
+                "*-client-*".to_string(),
+                "transport.socket.ssl-enabled".to_string(),
+                on_off(enabled).to_string(),
+            ),
+            XlatorOption::Raw {
+                ref xlator,
+                ref key,
+                ref value,
+            } => (xlator.clone(), key.clone(), value.clone()),
+        };
+        Ok((CString::new(xlator)?, CString::new(key)?, CString::new(value)?))
+    }
+}
+
+/// A client volfile used in place of fetching one from glusterd
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Volfile {
+    /// Path to a volfile on the local filesystem
+    Path(PathBuf),
+    /// The contents of a volfile
+    Text(String),
+}
+
+/// Builds a connection to a GlusterFS volume.  Every server added is
+/// registered with gfapi so that if the first glusterd is unreachable the
+/// next one is tried.  If a volfile is given the client graph is built from
+/// it and no management daemon is contacted.
+#[derive(Clone, Debug)]
+pub struct GlusterBuilder {
+    volume_name: String,
+    servers: Vec<VolfileServer>,
+    volfile: Option<Volfile>,
+    xlator_options: Vec<XlatorOption>,
+    subdir: Option<PathBuf>,
+    forward_logging: Option<GlusterLogLevel>,
+}
+
+impl GlusterBuilder {
+    /// volume_name may include a subdirectory, for example "volume/sub/dir",
+    /// in which case the connection is rooted at that subdirectory.
+    pub fn new(volume_name: &str) -> GlusterBuilder {
+        let (volume_name, subdir) = match volume_name.find('/') {
+            Some(i) => (
+                &volume_name[..i],
+                Some(Path::new("/").join(&volume_name[i + 1..])),
+            ),
+            None => (volume_name, None),
+        };
+        GlusterBuilder {
+            volume_name: volume_name.to_string(),
+            servers: Vec::new(),
+            volfile: None,
+            xlator_options: Vec::new(),
+            subdir,
+            forward_logging: None,
+        }
+    }
+
+    /// Root the connection at a subdirectory of the volume, the way a
+    /// FUSE subdir mount works.  Paths used with the resulting Gluster are
+    /// resolved beneath this directory.  ".." can't climb above it and
+    /// symlinks, absolute ones included, resolve inside it.
+    /// This is enforced on the client, not by the server.  Symlinks are
+    /// checked just before each call, so a directory swapped for a symlink
+    /// by another thread or client in between can still redirect that
+    /// call.  Tenants that don't trust each other need separate volumes.
+    /// The checks cost an lstat for each component of every path, so
+    /// deep paths are slower than on a plain mount.
+    pub fn subdir(mut self, subdir: &Path) -> GlusterBuilder {
+        self.subdir = Some(Path::new("/").join(subdir));
+        self
+    }
+
+    /// Add a volfile server.  Servers are tried in the order they are added.
+    pub fn server(mut self, server: VolfileServer) -> GlusterBuilder {
+        self.servers.push(server);
+        self
+    }
+
+    /// Add several volfile servers at once
+    pub fn servers<I>(mut self, servers: I) -> GlusterBuilder
+    where
+        I: IntoIterator<Item = VolfileServer>,
+    {
+        self.servers.extend(servers);
+        self
+    }
+
+    /// Build the client graph from a local volfile instead of glusterd.
+    /// Any volfile servers are ignored when this is set.
+    pub fn volfile(mut self, volfile: Volfile) -> GlusterBuilder {
+        self.volfile = Some(volfile);
+        self
+    }
+
+    /// Set an option on the client graph.  Options are applied before the
+    /// graph is built.
+    pub fn xlator_option(mut self, option: XlatorOption) -> GlusterBuilder {
+        self.xlator_options.push(option);
+        self
+    }
+
+    /// Set an option on an xlator by name.  See XlatorOption::Raw
+    pub fn raw_xlator_option(self, xlator: &str, key: &str, value: &str) -> GlusterBuilder {
+        self.xlator_option(XlatorOption::Raw {
+            xlator: xlator.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    /// Send gfapi's log messages to the log crate instead of a file.
+    /// Messages at or above loglevel are forwarded, starting with those
+    /// written while connecting.  Reconnects by SupervisedGluster reuse
+    /// the builder so they keep forwarding.
+    pub fn forward_logging(mut self, loglevel: GlusterLogLevel) -> GlusterBuilder {
+        self.forward_logging = Some(loglevel);
+        self
+    }
+
+    /// Connect to the volume and return a connection handle glfs_t
+    pub fn connect(&self) -> Result<Gluster, GlusterError> {
+        if self.servers.is_empty() && self.volfile.is_none() {
+            return Err(GlusterError::new(
+                "At least one volfile server or a volfile is required".into(),
+            ));
+        }
+        let vol_name = CString::new(self.volume_name.as_str())?;
+        let subdir = match self.subdir {
+            Some(ref subdir) => Some(validate_subdir(subdir)?),
+            None => None,
+        };
+        let mut server_args = Vec::with_capacity(self.servers.len());
+        for server in &self.servers {
+            server_args.push(server.to_args()?);
+        }
+        let mut option_args = Vec::with_capacity(self.xlator_options.len());
+        for option in &self.xlator_options {
+            option_args.push(option.to_args()?);
+        }
+        // gfapi only accepts a volfile path so in memory volfiles are
+        // written out to a temporary file that lives until glfs_init returns.
+        let volfile = match self.volfile {
+            Some(Volfile::Path(ref path)) => Some(TempVolfile::existing(path)),
+            Some(Volfile::Text(ref text)) => Some(TempVolfile::write(text)?),
+            None => None,
+        };
+        let volfile_path = match volfile {
+            Some(ref v) => Some(CString::new(v.path.as_os_str().as_bytes())?),
+            None => None,
+        };
         unsafe {
             let cluster_handle = glfs_new(vol_name.as_ptr());
             if cluster_handle.is_null() {
                 return Err(GlusterError::new("glfs_new failed".to_string()));
             }
-            let ret_code = glfs_set_volfile_server(
-                cluster_handle,
-                vol_transport.as_ptr(),
-                vol_host.as_ptr(),
-                port as ::libc::c_int,
-            );
-            if ret_code < 0 {
-                // We call glfs_fini here because Gluster hasn't been created yet
-                // so Drop won't be run.
-                glfs_fini(cluster_handle);
-                return Err(GlusterError::new(get_error()));
+            if let Some(loglevel) = self.forward_logging {
+                if let Err(e) = forward_logging(cluster_handle, loglevel) {
+                    glfs_fini(cluster_handle);
+                    return Err(e);
+                }
+            }
+            match volfile_path {
+                Some(ref path) => {
+                    let ret_code = glfs_set_volfile(cluster_handle, path.as_ptr());
+                    if ret_code < 0 {
+                        // We call glfs_fini here because Gluster hasn't been created yet
+                        // so Drop won't be run.
+                        let error = get_error();
+                        glfs_fini(cluster_handle);
+                        return Err(error);
+                    }
+                }
+                None => {
+                    for (transport, host, port) in &server_args {
+                        let ret_code = glfs_set_volfile_server(
+                            cluster_handle,
+                            transport.as_ptr(),
+                            host.as_ptr(),
+                            *port as ::libc::c_int,
+                        );
+                        if ret_code < 0 {
+                            let error = get_error();
+                            glfs_fini(cluster_handle);
+                            return Err(error);
+                        }
+                    }
+                }
+            }
+            for (xlator, key, value) in &option_args {
+                let ret_code = glfs_set_xlator_option(
+                    cluster_handle,
+                    xlator.as_ptr(),
+                    key.as_ptr(),
+                    value.as_ptr(),
+                );
+                if ret_code < 0 {
+                    let error = get_error();
+                    glfs_fini(cluster_handle);
+                    return Err(error);
+                }
             }
 
             let ret_code = glfs_init(cluster_handle);
             if ret_code < 0 {
-                // We call glfs_fini here because Gluster hasn't been created yet
-                // so Drop won't be run.
+                let error = get_error();
                 glfs_fini(cluster_handle);
-                return Err(GlusterError::new(get_error()));
+                return Err(error);
+            }
+            let gluster = Gluster {
+                cluster_handle,
+                root: None,
+                upcalls: Box::default(),
+            };
+            match subdir {
+                Some(subdir) => gluster.mount_subdir(subdir),
+                None => Ok(gluster),
+            }
+        }
+    }
+}
+
+/// Check that a subdirectory is absolute and only made of normal components
+fn validate_subdir(subdir: &Path) -> Result<PathBuf, GlusterError> {
+    let mut validated = PathBuf::from("/");
+    for component in subdir.components() {
+        match component {
+            Component::RootDir => {}
+            Component::Normal(name) => validated.push(name),
+            _ => {
+                return Err(GlusterError::new(format!(
+                    "Invalid subdirectory {}: only plain directory names are allowed",
+                    subdir.display()
+                )));
+            }
+        }
+    }
+    if validated == Path::new("/") {
+        return Err(GlusterError::new(format!(
+            "Invalid subdirectory {}",
+            subdir.display()
+        )));
+    }
+    Ok(validated)
+}
+
+/// A volfile on disk.  If it was written from in memory text it is
+/// removed again on drop.
+struct TempVolfile {
+    path: PathBuf,
+    remove: bool,
+}
+
+impl TempVolfile {
+    fn existing(path: &Path) -> TempVolfile {
+        TempVolfile {
+            path: path.to_path_buf(),
+            remove: false,
+        }
+    }
+
+    fn write(text: &str) -> Result<TempVolfile, GlusterError> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "gfapi-{}-{}.vol",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        // Client volfiles can carry brick credentials so keep it private
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        let volfile = TempVolfile { path, remove: true };
+        file.write_all(text.as_bytes())?;
+        Ok(volfile)
+    }
+}
+
+impl Drop for TempVolfile {
+    fn drop(&mut self) {
+        if self.remove {
+            if let Err(e) = fs::remove_file(&self.path) {
+                error!("Unable to remove {}: {:?}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// Most symlinks followed while resolving one path, the same as Linux
+pub(crate) const MAX_SYMLINKS: usize = 40;
+
+/// An owned path component for resolving paths beneath a subdirectory
+pub(crate) enum Step {
+    Root,
+    Parent,
+    Name(OsString),
+}
+
+pub(crate) fn steps(path: &Path) -> impl DoubleEndedIterator<Item = Step> + '_ {
+    path.components().filter_map(|component| match component {
+        Component::RootDir => Some(Step::Root),
+        Component::ParentDir => Some(Step::Parent),
+        Component::Normal(name) => Some(Step::Name(name.to_os_string())),
+        Component::CurDir | Component::Prefix(_) => None,
+    })
+}
+
+fn lstat_cpath(cluster_handle: *mut glfs, path: &CStr) -> Result<stat, GlusterError> {
+    unsafe {
+        let mut stat_buf: stat = zeroed();
+        let ret_code = glfs_lstat(cluster_handle, path.as_ptr(), &mut stat_buf);
+        if ret_code < 0 {
+            return Err(get_error());
+        }
+        Ok(stat_buf)
+    }
+}
+
+fn readlink_cpath(cluster_handle: *mut glfs, path: &CStr) -> Result<PathBuf, GlusterError> {
+    let mut buf: Vec<u8> = Vec::with_capacity(PATH_MAX as usize);
+    unsafe {
+        let len = glfs_readlink(
+            cluster_handle,
+            path.as_ptr(),
+            buf.as_mut_ptr() as *mut c_char,
+            buf.capacity(),
+        );
+        if len < 0 {
+            return Err(get_error());
+        }
+        buf.set_len(len as usize);
+    }
+    // Some versions of gluster count the trailing nul
+    if buf.last() == Some(&0) {
+        buf.pop();
+    }
+    Ok(PathBuf::from(OsString::from_vec(buf)))
+}
+
+impl Gluster {
+    /// Connect to a GlusterFS cluster and return a connection handle glfs_t
+    /// port is usually 24007 but may differ depending on how the service was configured
+    /// Use GlusterBuilder to connect with more than one server or a different
+    /// transport.
+    pub fn connect(volume_name: &str, server: &str, port: u16) -> Result<Gluster, GlusterError> {
+        GlusterBuilder::new(volume_name)
+            .server(VolfileServer::tcp(server, port))
+            .connect()
+    }
+
+    /// Build the client graph from a local volfile rather than fetching it
+    /// from glusterd.  This is useful for talking to bricks directly.
+    pub fn from_volfile(volume_name: &str, volfile: &Path) -> Result<Gluster, GlusterError> {
+        GlusterBuilder::new(volume_name)
+            .volfile(Volfile::Path(volfile.to_path_buf()))
+            .connect()
+    }
+
+    /// Build the client graph from the text of a volfile
+    pub fn from_volfile_str(volume_name: &str, volfile: &str) -> Result<Gluster, GlusterError> {
+        GlusterBuilder::new(volume_name)
+            .volfile(Volfile::Text(volfile.to_string()))
+            .connect()
+    }
+
+    /// Root this connection at subdir.  On failure the connection is dropped.
+    fn mount_subdir(mut self, subdir: PathBuf) -> Result<Gluster, GlusterError> {
+        let stat_buf = match self.lsstat(&subdir) {
+            Ok(stat_buf) => stat_buf,
+            Err(e) => {
+                let reason = if e.is_errno(ENOENT) {
+                    "it does not exist".to_string()
+                } else if e.is_errno(EACCES) || e.is_errno(EPERM) {
+                    "permission denied".to_string()
+                } else {
+                    e.to_string()
+                };
+                return Err(GlusterError::new(format!(
+                    "Unable to mount subdirectory {}: {}",
+                    subdir.display(),
+                    reason
+                )));
+            }
+        };
+        // The root itself is resolved by gfapi on every call so it must
+        // not be a symlink
+        if stat_buf.st_mode & S_IFMT != S_IFDIR {
+            return Err(GlusterError::new(format!(
+                "Unable to mount subdirectory {}: not a directory",
+                subdir.display()
+            )));
+        }
+        self.chdir(&subdir)?;
+        self.root = Some(subdir);
+        Ok(self)
+    }
+
+    /// The subdirectory this connection is rooted at if any
+    pub fn subdir(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    /// Convert a path on this connection into a C string for gfapi.
+    /// With a subdirectory mount the path is resolved beneath the root,
+    /// including a symlink in the last component.  Relative paths resolve
+    /// against the working directory.
+    pub(crate) fn to_cpath(&self, path: &Path) -> Result<CString, GlusterError> {
+        self.resolve_cpath(path, true)
+    }
+
+    /// Like to_cpath but a symlink in the last component is left for the
+    /// call to act on rather than followed.  Used by the calls that don't
+    /// follow symlinks themselves such as lstat, unlink and rename.
+    pub(crate) fn to_cpath_nofollow(&self, path: &Path) -> Result<CString, GlusterError> {
+        self.resolve_cpath(path, false)
+    }
+
+    fn resolve_cpath(&self, path: &Path, follow: bool) -> Result<CString, GlusterError> {
+        let resolved = match self.root {
+            Some(ref root) => self.resolve_beneath(root, path, follow)?,
+            None => path.to_path_buf(),
+        };
+        Ok(CString::new(resolved.as_os_str().as_bytes())?)
+    }
+
+    /// Resolve path the way the kernel would if root were the root
+    /// directory.  gfapi follows symlinks itself, from the root of the
+    /// volume for absolute targets, so every symlink on the way is read
+    /// here and replaced by its target.  The path handed to gfapi then
+    /// only has a symlink in the last component when follow is false.
+    /// ".." may not climb above root.
+    /// Every component below root costs an lstat round trip unless
+    /// md-cache has it.  Caching a handle for root wouldn't save those
+    /// since gfapi has no path calls relative to a handle, and caching the
+    /// lookups themselves would miss symlinks swapped in by other clients.
+    fn resolve_beneath(
+        &self,
+        root: &Path,
+        path: &Path,
+        follow: bool,
+    ) -> Result<PathBuf, GlusterError> {
+        let escapes =
+            || GlusterError::new(format!("{} escapes the subdirectory mount", path.display()));
+        let mut resolved = root.to_path_buf();
+        if path.is_relative() {
+            let cwd = self.volume_cwd()?;
+            match cwd.strip_prefix(root) {
+                Ok(rest) => resolved.push(rest),
+                Err(_) => return Err(escapes()),
+            }
+        }
+        // Components left to resolve with the next one last
+        let mut pending: Vec<Step> = steps(path).rev().collect();
+        let mut links = 0;
+        while let Some(step) = pending.pop() {
+            match step {
+                Step::Root => resolved = root.to_path_buf(),
+                Step::Parent => {
+                    if resolved == root {
+                        return Err(escapes());
+                    }
+                    resolved.pop();
+                }
+                Step::Name(name) => {
+                    resolved.push(name);
+                    let last = pending.is_empty();
+                    if last && !follow {
+                        break;
+                    }
+                    let cpath = CString::new(resolved.as_os_str().as_bytes())?;
+                    let stat_buf = match lstat_cpath(self.cluster_handle, &cpath) {
+                        Ok(stat_buf) => stat_buf,
+                        // Creating it is up to the call
+                        Err(ref e) if last && e.is_errno(ENOENT) => break,
+                        Err(e) => return Err(e),
+                    };
+                    if stat_buf.st_mode & S_IFMT != S_IFLNK {
+                        continue;
+                    }
+                    links += 1;
+                    if links > MAX_SYMLINKS {
+                        return Err(Error::from_raw_os_error(ELOOP).into());
+                    }
+                    // Carry on with the target in place of the symlink.
+                    // Absolute targets start over at root.
+                    let target = readlink_cpath(self.cluster_handle, &cpath)?;
+                    resolved.pop();
+                    pending.extend(steps(&target).rev());
+                }
             }
-            Ok(Gluster { cluster_handle })
         }
+        Ok(resolved)
     }
 
     /// This function specifies logging parameters for the virtual mount.
@@ -361,7 +1049,32 @@ impl Gluster {
         unsafe {
             let ret_code = glfs_set_logging(self.cluster_handle, path.as_ptr(), loglevel as i32);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the directory that statedumps of this client are written to.
+    /// The default is /var/run/gluster
+    pub fn set_statedump_path(&self, path: &Path) -> Result<(), GlusterError> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        unsafe {
+            let ret_code = glfs_set_statedump_path(self.cluster_handle, path.as_ptr());
+            if ret_code < 0 {
+                return Err(get_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a sysrq key to the client.  SysRqKey::Statedump writes
+    /// glusterdump.<pid>.dump.<timestamp> to the statedump path.
+    pub fn sysrq(&self, key: SysRqKey) -> Result<(), GlusterError> {
+        unsafe {
+            let ret_code = glfs_sysrq(self.cluster_handle, key.into());
+            if ret_code < 0 {
+                return Err(get_error());
             }
         }
         Ok(())
@@ -372,9 +1085,8 @@ impl Gluster {
     /// can parse it and find subvolumes to do things like split-brain resolution
     /// or custom layouts.
     /// Note that the volume must be started (not necessarily mounted) for this
-    /// to work.  Also this function isn't very useful at the moment.  It needs
-    /// to be parsed into a volume graph before it's really usable.  
-    // TODO: Change this from String to a struct
+    /// to work.  Use volume_graph to get the volfile parsed into a
+    /// VolumeGraph.
     pub fn get_volfile(&self) -> Result<String, GlusterError> {
         // Start with 1K buffer and see if that works.  Even small clusters
         // have pretty large volfiles.
@@ -442,7 +1154,7 @@ impl Gluster {
                 buff.capacity(),
             );
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
             // Inform Rust how many bytes gluster copied into the buffer
             buff.set_len(ret_code as usize);
@@ -452,13 +1164,17 @@ impl Gluster {
     }
 
     pub fn open(&self, path: &Path, flags: i32) -> Result<GlusterFile, GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = if flags & O_NOFOLLOW != 0 {
+            self.to_cpath_nofollow(path)?
+        } else {
+            self.to_cpath(path)?
+        };
         unsafe {
             let file_handle = glfs_open(self.cluster_handle, path.as_ptr(), flags);
             if file_handle.is_null() {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
-            Ok(GlusterFile { file_handle })
+            Ok(GlusterFile::from_raw(file_handle))
         }
     }
 
@@ -468,40 +1184,44 @@ impl Gluster {
         flags: i32,
         mode: mode_t,
     ) -> Result<GlusterFile, GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = if flags & O_NOFOLLOW != 0 {
+            self.to_cpath_nofollow(path)?
+        } else {
+            self.to_cpath(path)?
+        };
         unsafe {
             let file_handle = glfs_creat(self.cluster_handle, path.as_ptr(), flags, mode);
             if file_handle.is_null() {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
-            Ok(GlusterFile { file_handle })
+            Ok(GlusterFile::from_raw(file_handle))
         }
     }
     pub fn truncate(&self, path: &Path, length: i64) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath(path)?;
 
         unsafe {
             let ret_code = glfs_truncate(self.cluster_handle, path.as_ptr(), length);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
     pub fn lsstat(&self, path: &Path) -> Result<stat, GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath_nofollow(path)?;
         unsafe {
             let mut stat_buf: stat = zeroed();
             let ret_code = glfs_lstat(self.cluster_handle, path.as_ptr(), &mut stat_buf);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
             Ok(stat_buf)
         }
     }
     /// Tests for the existance of a file.  Returns true/false respectively.
     pub fn exists(&self, path: &Path) -> Result<bool, GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath(path)?;
         unsafe {
             let mut stat_buf: stat = zeroed();
             let ret_code = glfs_stat(self.cluster_handle, path.as_ptr(), &mut stat_buf);
@@ -510,60 +1230,63 @@ impl Gluster {
                 if error == Errno(ENOENT) {
                     return Ok(false);
                 }
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
             Ok(true)
         }
     }
 
     pub fn statvfs(&self, path: &Path) -> Result<statvfs, GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath(path)?;
         unsafe {
             let mut stat_buf: statvfs = zeroed();
             let ret_code = glfs_statvfs(self.cluster_handle, path.as_ptr(), &mut stat_buf);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
             Ok(stat_buf)
         }
     }
 
     pub fn stat(&self, path: &Path) -> Result<stat, GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath(path)?;
         unsafe {
             let mut stat_buf: stat = zeroed();
             let ret_code = glfs_stat(self.cluster_handle, path.as_ptr(), &mut stat_buf);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
             Ok(stat_buf)
         }
     }
     pub fn access(&self, path: &Path, mode: i32) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath(path)?;
         unsafe {
             let ret_code = glfs_access(self.cluster_handle, path.as_ptr(), mode);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
 
+    /// Create newpath as a symlink to oldpath.  oldpath is stored as
+    /// given.  On a subdirectory mount absolute targets resolve from the
+    /// subdirectory when they are followed through this library.
     pub fn symlink(&self, oldpath: &Path, newpath: &Path) -> Result<(), GlusterError> {
         let old_path = CString::new(oldpath.as_os_str().as_bytes())?;
-        let new_path = CString::new(newpath.as_os_str().as_bytes())?;
+        let new_path = self.to_cpath_nofollow(newpath)?;
         unsafe {
             let ret_code = glfs_symlink(self.cluster_handle, old_path.as_ptr(), new_path.as_ptr());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
 
     pub fn readlink(&self, path: &Path, buf: &mut [u8]) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath_nofollow(path)?;
         unsafe {
             let ret_code = glfs_readlink(
                 self.cluster_handle,
@@ -572,50 +1295,50 @@ impl Gluster {
                 buf.len(),
             );
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
 
     pub fn mknod(&self, path: &Path, mode: mode_t, dev: dev_t) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath_nofollow(path)?;
         unsafe {
             let ret_code = glfs_mknod(self.cluster_handle, path.as_ptr(), mode, dev);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
 
     pub fn mkdir(&self, path: &Path, mode: mode_t) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath_nofollow(path)?;
         unsafe {
             let ret_code = glfs_mkdir(self.cluster_handle, path.as_ptr(), mode);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
 
     pub fn unlink(&self, path: &Path) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath_nofollow(path)?;
         unsafe {
             let ret_code = glfs_unlink(self.cluster_handle, path.as_ptr());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
     pub fn rmdir(&self, path: &Path) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath_nofollow(path)?;
         unsafe {
             let ret_code = glfs_rmdir(self.cluster_handle, path.as_ptr());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -720,31 +1443,31 @@ impl Gluster {
     }
 
     pub fn rename(&self, oldpath: &Path, newpath: &Path) -> Result<(), GlusterError> {
-        let old_path = CString::new(oldpath.as_os_str().as_bytes())?;
-        let new_path = CString::new(newpath.as_os_str().as_bytes())?;
+        let old_path = self.to_cpath_nofollow(oldpath)?;
+        let new_path = self.to_cpath_nofollow(newpath)?;
         unsafe {
             let ret_code = glfs_rename(self.cluster_handle, old_path.as_ptr(), new_path.as_ptr());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
 
     pub fn link(&self, oldpath: &Path, newpath: &Path) -> Result<(), GlusterError> {
-        let old_path = CString::new(oldpath.as_os_str().as_bytes())?;
-        let new_path = CString::new(newpath.as_os_str().as_bytes())?;
+        let old_path = self.to_cpath_nofollow(oldpath)?;
+        let new_path = self.to_cpath_nofollow(newpath)?;
         unsafe {
             let ret_code = glfs_link(self.cluster_handle, old_path.as_ptr(), new_path.as_ptr());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
 
     pub fn opendir(&self, path: &Path) -> Result<GlusterDirectory, GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath(path)?;
         unsafe {
             let dir_handle = glfs_opendir(self.cluster_handle, path.as_ptr());
             Ok(GlusterDirectory { dir_handle })
@@ -753,90 +1476,121 @@ impl Gluster {
 
     // Readdir plus opendir
     pub fn opendir_plus(&self, path: &Path) -> Result<GlusterDirectoryPlus, GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath(path)?;
         unsafe {
             let dir_handle = glfs_opendir(self.cluster_handle, path.as_ptr());
             Ok(GlusterDirectoryPlus { dir_handle })
         }
     }
 
-    pub fn getxattr(&self, path: &Path, name: &str) -> Result<String, GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+    /// Read an xattr value as raw bytes.  The size is asked for first so
+    /// values of any length are returned whole.
+    fn getxattr_bytes(&self, path: &CStr, name: &str) -> Result<Vec<u8>, GlusterError> {
         let name = CString::new(name)?;
-        let mut xattr_val_buff: Vec<u8> = Vec::with_capacity(1024);
+        sized_fetch(
+            |buf, size| unsafe {
+                glfs_getxattr(self.cluster_handle, path.as_ptr(), name.as_ptr(), buf, size)
+            },
+            get_error,
+        )
+    }
+
+    /// The virtual path gluster resolves to the file or directory with
+    /// this gfid.  It is relative to the volume root, not the subdir mount.
+    fn gfid_cpath(gfid: &Uuid) -> Result<CString, GlusterError> {
+        Ok(CString::new(format!("/.gfid/{}", gfid))?)
+    }
+
+    /// Return the gfid of path from the glusterfs.gfid virtual xattr
+    pub fn gfid(&self, path: &Path) -> Result<Uuid, GlusterError> {
+        let path = self.to_cpath(path)?;
+        let gfid = self.getxattr_bytes(&path, "glusterfs.gfid")?;
+        Ok(Uuid::from_slice(&gfid)?)
+    }
+
+    /// Open the file with this gfid through the .gfid virtual directory.
+    /// On a subdirectory mount the file has to be inside the subdirectory,
+    /// which is checked with gfid_to_path and so needs the
+    /// storage.build-pgfid volume option.
+    pub fn open_by_gfid(&self, gfid: &Uuid, flags: i32) -> Result<GlusterFile, GlusterError> {
+        self.check_gfid_beneath(gfid)?;
+        let path = Gluster::gfid_cpath(gfid)?;
         unsafe {
-            let ret_code = glfs_getxattr(
-                self.cluster_handle,
-                path.as_ptr(),
-                name.as_ptr(),
-                xattr_val_buff.as_mut_ptr() as *mut c_void,
-                xattr_val_buff.len(),
-            );
-            if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+            let file_handle = glfs_open(self.cluster_handle, path.as_ptr(), flags);
+            if file_handle.is_null() {
+                return Err(get_error());
             }
-            // Set the buffer to the size of bytes read into it
-            xattr_val_buff.set_len(ret_code as usize);
-            Ok(String::from_utf8_lossy(&xattr_val_buff).into_owned())
+            Ok(GlusterFile::from_raw(file_handle))
+        }
+    }
+
+    /// Find a path for the file or directory with this gfid using the
+    /// glusterfs.ancestry.path virtual xattr.  Resolving files needs the
+    /// storage.build-pgfid volume option.  Files with several hard links
+    /// resolve to one of them.  With a subdir mount the returned path is
+    /// relative to the subdir and gfids outside of it are an error.
+    pub fn gfid_to_path(&self, gfid: &Uuid) -> Result<PathBuf, GlusterError> {
+        let path = Gluster::gfid_cpath(gfid)?;
+        let mut ancestry = self.getxattr_bytes(&path, "glusterfs.ancestry.path")?;
+        if let Some(end) = ancestry.iter().position(|b| *b == 0) {
+            ancestry.truncate(end);
+        }
+        let path = PathBuf::from(String::from_utf8(ancestry)?);
+        match self.root {
+            Some(ref root) => match path.strip_prefix(root) {
+                Ok(relative) => Ok(Path::new("/").join(relative)),
+                Err(_) => Err(GlusterError::new(format!(
+                    "{} is outside of the subdirectory mount",
+                    path.display()
+                ))),
+            },
+            None => Ok(path),
         }
     }
 
+    /// On a subdirectory mount fail unless the file or directory with this
+    /// gfid is inside the subdirectory.  This is looked up the same way as
+    /// gfid_to_path.
+    pub(crate) fn check_gfid_beneath(&self, gfid: &Uuid) -> Result<(), GlusterError> {
+        if self.root.is_some() {
+            self.gfid_to_path(gfid)?;
+        }
+        Ok(())
+    }
+
+    pub fn getxattr(&self, path: &Path, name: &str) -> Result<String, GlusterError> {
+        let path = self.to_cpath(path)?;
+        let value = self.getxattr_bytes(&path, name)?;
+        Ok(String::from_utf8_lossy(&value).into_owned())
+    }
+
     pub fn lgetxattr(&self, path: &Path, name: &str) -> Result<String, GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath_nofollow(path)?;
         let name = CString::new(name)?;
-        let mut xattr_val_buff: Vec<u8> = Vec::with_capacity(1024);
-        unsafe {
-            let ret_code = glfs_lgetxattr(
-                self.cluster_handle,
-                path.as_ptr(),
-                name.as_ptr(),
-                xattr_val_buff.as_mut_ptr() as *mut c_void,
-                xattr_val_buff.len(),
-            );
-            if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
-            }
-            // Set the buffer to the size of bytes read into it
-            xattr_val_buff.set_len(ret_code as usize);
-            Ok(String::from_utf8_lossy(&xattr_val_buff).into_owned())
-        }
+        let value = sized_fetch(
+            |buf, size| unsafe {
+                glfs_lgetxattr(self.cluster_handle, path.as_ptr(), name.as_ptr(), buf, size)
+            },
+            get_error,
+        )?;
+        Ok(String::from_utf8_lossy(&value).into_owned())
     }
 
     pub fn listxattr(&self, path: &Path) -> Result<String, GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
-        let mut xattr_val_buff: Vec<u8> = Vec::with_capacity(1024);
-        unsafe {
-            let ret_code = glfs_listxattr(
-                self.cluster_handle,
-                path.as_ptr(),
-                xattr_val_buff.as_mut_ptr() as *mut c_void,
-                xattr_val_buff.len(),
-            );
-            if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
-            }
-            // Set the buffer to the size of bytes read into it
-            xattr_val_buff.set_len(ret_code as usize);
-            Ok(String::from_utf8_lossy(&xattr_val_buff).into_owned())
-        }
+        let path = self.to_cpath(path)?;
+        let list = sized_fetch(
+            |buf, size| unsafe { glfs_listxattr(self.cluster_handle, path.as_ptr(), buf, size) },
+            get_error,
+        )?;
+        Ok(String::from_utf8_lossy(&list).into_owned())
     }
     pub fn llistxattr(&self, path: &Path) -> Result<String, GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
-        let mut xattr_val_buff: Vec<u8> = Vec::with_capacity(1024);
-        unsafe {
-            let ret_code = glfs_llistxattr(
-                self.cluster_handle,
-                path.as_ptr(),
-                xattr_val_buff.as_mut_ptr() as *mut c_void,
-                xattr_val_buff.len(),
-            );
-            if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
-            }
-            // Set the buffer to the size of bytes read into it
-            xattr_val_buff.set_len(ret_code as usize);
-            Ok(String::from_utf8_lossy(&xattr_val_buff).into_owned())
-        }
+        let path = self.to_cpath_nofollow(path)?;
+        let list = sized_fetch(
+            |buf, size| unsafe { glfs_llistxattr(self.cluster_handle, path.as_ptr(), buf, size) },
+            get_error,
+        )?;
+        Ok(String::from_utf8_lossy(&list).into_owned())
     }
     pub fn setxattr(
         &self,
@@ -845,7 +1599,7 @@ impl Gluster {
         value: &[u8],
         flags: i32,
     ) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath(path)?;
         let name = CString::new(name)?;
         unsafe {
             let ret_code = glfs_setxattr(
@@ -857,7 +1611,7 @@ impl Gluster {
                 flags,
             );
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -870,7 +1624,7 @@ impl Gluster {
         flags: i32,
     ) -> Result<(), GlusterError> {
         let name = CString::new(name)?;
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath_nofollow(path)?;
         unsafe {
             let ret_code = glfs_lsetxattr(
                 self.cluster_handle,
@@ -881,50 +1635,66 @@ impl Gluster {
                 flags,
             );
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
     pub fn removexattr(&self, path: &Path, name: &str) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath(path)?;
         let name = CString::new(name)?;
         unsafe {
             let ret_code = glfs_removexattr(self.cluster_handle, path.as_ptr(), name.as_ptr());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
     pub fn lremovexattr(&self, path: &Path, name: &str) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath_nofollow(path)?;
         let name = CString::new(name)?;
         unsafe {
             let ret_code = glfs_lremovexattr(self.cluster_handle, path.as_ptr(), name.as_ptr());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
-    pub fn getcwd(&self) -> Result<String, GlusterError> {
+    /// The working directory as a path from the root of the volume
+    fn volume_cwd(&self) -> Result<PathBuf, GlusterError> {
         let mut cwd_val_buff: Vec<u8> = Vec::with_capacity(1024);
         unsafe {
             let cwd = glfs_getcwd(
                 self.cluster_handle,
                 cwd_val_buff.as_mut_ptr() as *mut i8,
-                cwd_val_buff.len(),
+                cwd_val_buff.capacity(),
             );
-            Ok(CStr::from_ptr(cwd).to_string_lossy().into_owned())
+            if cwd.is_null() {
+                return Err(get_error());
+            }
+            Ok(PathBuf::from(OsStr::from_bytes(CStr::from_ptr(cwd).to_bytes())))
         }
     }
+    pub fn getcwd(&self) -> Result<String, GlusterError> {
+        let cwd = self.volume_cwd()?;
+        // Hide the subdirectory mount from the caller
+        let cwd = match self.root {
+            Some(ref root) => match cwd.strip_prefix(root) {
+                Ok(rest) => Path::new("/").join(rest),
+                Err(_) => cwd,
+            },
+            None => cwd,
+        };
+        Ok(cwd.to_string_lossy().into_owned())
+    }
     pub fn chdir(&self, path: &Path) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath(path)?;
         unsafe {
             let ret_code = glfs_chdir(self.cluster_handle, path.as_ptr());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -932,11 +1702,11 @@ impl Gluster {
     /// times[0] specifies the new "last access time" (atime);
     /// times[1] specifies the new "last modification time" (mtime).
     pub fn utimens(&self, path: &Path, times: &[timespec; 2]) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath(path)?;
         unsafe {
             let ret_code = glfs_utimens(self.cluster_handle, path.as_ptr(), times.as_ptr());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -945,42 +1715,42 @@ impl Gluster {
     /// times[0] specifies the new "last access time" (atime);
     /// times[1] specifies the new "last modification time" (mtime).
     pub fn lutimens(&self, path: &Path, times: &[timespec; 2]) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath_nofollow(path)?;
         unsafe {
             let ret_code = glfs_lutimens(self.cluster_handle, path.as_ptr(), times.as_ptr());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
     pub fn chmod(&self, path: &Path, mode: mode_t) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath(path)?;
         unsafe {
             let ret_code = glfs_chmod(self.cluster_handle, path.as_ptr(), mode);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
     pub fn chown(&self, path: &Path, uid: u32, gid: u32) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath(path)?;
         unsafe {
             let ret_code = glfs_chown(self.cluster_handle, path.as_ptr(), uid, gid);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
 
     pub fn lchown(&self, path: &Path, uid: u32, gid: u32) -> Result<(), GlusterError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let path = self.to_cpath_nofollow(path)?;
         unsafe {
             let ret_code = glfs_lchown(self.cluster_handle, path.as_ptr(), uid, gid);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -988,14 +1758,116 @@ impl Gluster {
 }
 
 impl GlusterFile {
-    pub fn read(
+    /// Read into buf from the current file offset and advance the offset
+    /// by the number of bytes read.  0 means the end of the file was reached.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, GlusterError> {
+        unsafe {
+            let read_size = glfs_read(
+                self.file_handle,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len(),
+                0,
+            );
+            if read_size < 0 {
+                return Err(get_error());
+            }
+            Ok(read_size as usize)
+        }
+    }
+
+    /// Read into buf at offset without moving the file offset
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, GlusterError> {
+        // [u8] is also a valid [MaybeUninit<u8>] and read_at_uninit only
+        // ever writes initialized bytes into it
+        let uninit = unsafe {
+            slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut MaybeUninit<u8>, buf.len())
+        };
+        Ok(self.read_at_uninit(uninit, offset)?.len())
+    }
+
+    /// Read into a buffer that doesn't need to be zeroed first.  Returns
+    /// the part of buf that was filled.  Useful for large buffers where
+    /// zeroing would cost more than the read.
+    pub fn read_at_uninit<'b>(
         &self,
-        fill_buffer: &mut Vec<u8>,
-        count: usize,
-        flags: i32,
-    ) -> Result<isize, GlusterError> {
-        self.pread(fill_buffer, count, 0, flags)
+        buf: &'b mut [MaybeUninit<u8>],
+        offset: u64,
+    ) -> Result<&'b mut [u8], GlusterError> {
+        let offset = to_offset(offset)?;
+        unsafe {
+            let read_size = glfs_pread(
+                self.file_handle,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len(),
+                offset,
+                0,
+                ptr::null_mut(),
+            );
+            if read_size < 0 {
+                return Err(get_error());
+            }
+            // gluster initialized the first read_size bytes
+            Ok(slice::from_raw_parts_mut(
+                buf.as_mut_ptr() as *mut u8,
+                read_size as usize,
+            ))
+        }
     }
+
+    /// Write buf at offset without moving the file offset.  Returns the
+    /// number of bytes written.
+    pub fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize, GlusterError> {
+        let offset = to_offset(offset)?;
+        unsafe {
+            let write_size = glfs_pwrite(
+                self.file_handle,
+                buf.as_ptr() as *const c_void,
+                buf.len(),
+                offset,
+                0,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            if write_size < 0 {
+                return Err(get_error());
+            }
+            Ok(write_size as usize)
+        }
+    }
+
+    /// Read from the current file offset to the end of the file and append
+    /// the data to buf.  Returns the number of bytes read.
+    pub fn read_to_end(&self, buf: &mut Vec<u8>) -> Result<usize, GlusterError> {
+        let start = buf.len();
+        loop {
+            if buf.capacity() - buf.len() < READ_CHUNK {
+                buf.reserve(READ_CHUNK);
+            }
+            let spare = buf.spare_capacity_mut();
+            let read_size = unsafe {
+                let read_size = glfs_read(
+                    self.file_handle,
+                    spare.as_mut_ptr() as *mut c_void,
+                    spare.len(),
+                    0,
+                );
+                if read_size < 0 {
+                    let e = get_error();
+                    if e.is_errno(EINTR) {
+                        continue;
+                    }
+                    return Err(e);
+                }
+                read_size as usize
+            };
+            if read_size == 0 {
+                return Ok(buf.len() - start);
+            }
+            // gluster initialized read_size bytes past the old length
+            unsafe { buf.set_len(buf.len() + read_size) };
+        }
+    }
+
     pub fn write(&self, buffer: &[u8], flags: i32) -> Result<isize, GlusterError> {
         unsafe {
             let write_size = glfs_write(
@@ -1005,72 +1877,48 @@ impl GlusterFile {
                 flags,
                 );
             if write_size < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
             Ok(write_size)
         }
     }
 
-    /*
-    pub fn write_async<F>(
-        &self,
-        file_handle: *mut Struct_glfs_fd,
-        buffer: &[u8],
-        flags: i32,
-        callback: F,
-        data: &mut ::libc::c_void,
-    ) -> Result<(), GlusterError>
-    where
-        F: Fn(*mut Struct_glfs_fd, isize, *mut ::libc::c_void),
-    {
-        let closure = Closure3::new(&callback);
-        let callback_ptr = closure.code_ptr();
-        unsafe {
-            let ret_code = glfs_write_async(
-                file_handle,
-                buffer.as_ptr() as *const c_void,
-                buffer.len(),
-                flags,
-                Some(*callback_ptr),
-                data,
-            );
-            if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
-            }
-        }
-        Ok(())
-    }
-    */
-    pub fn readv(&self, iov: &mut [&mut [u8]], flags: i32) -> Result<isize, GlusterError> {
+    /// Read into several buffers in turn from the current file offset.
+    /// IoSliceMut has the same layout as iovec so it is passed straight
+    /// to gluster.
+    pub fn readv(&self, iov: &mut [IoSliceMut<'_>], flags: i32) -> Result<isize, GlusterError> {
         unsafe {
             let read_size = glfs_readv(
                 self.file_handle,
                 iov.as_ptr() as *const iovec,
-                iov.len() as i32,
+                iov_count(iov.len()),
                 flags,
             );
             if read_size < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
             Ok(read_size)
         }
     }
-    pub fn writev(&self, iov: &[&[u8]], flags: i32) -> Result<isize, GlusterError> {
+    /// Write several buffers in turn at the current file offset
+    pub fn writev(&self, iov: &[IoSlice<'_>], flags: i32) -> Result<isize, GlusterError> {
         unsafe {
             let write_size = glfs_writev(
                 self.file_handle,
                 iov.as_ptr() as *const iovec,
-                iov.len() as i32,
+                iov_count(iov.len()),
                 flags,
             );
             if write_size < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
             Ok(write_size)
         }
     }
 
-    /// Read into fill_buffer at offset and return the number of bytes read
+    /// Read up to count bytes at offset into fill_buffer, replacing its
+    /// contents.  Returns the number of bytes read.  read_at reads into a
+    /// slice instead.
     pub fn pread(
         &self,
         fill_buffer: &mut Vec<u8>,
@@ -1078,6 +1926,8 @@ impl GlusterFile {
         offset: i64,
         flags: i32,
     ) -> Result<isize, GlusterError> {
+        fill_buffer.clear();
+        fill_buffer.reserve(count);
         unsafe {
             let read_size = glfs_pread(
                 self.file_handle,
@@ -1088,12 +1938,14 @@ impl GlusterFile {
                 std::ptr::null_mut(),
             );
             if read_size < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
             fill_buffer.set_len(read_size as usize);
             Ok(read_size)
         }
     }
+    /// Write the first count bytes of buffer at offset.  count may not be
+    /// larger than buffer.
     pub fn pwrite(
         &self,
         buffer: &[u8],
@@ -1101,6 +1953,13 @@ impl GlusterFile {
         offset: i64,
         flags: i32,
     ) -> Result<isize, GlusterError> {
+        if count > buffer.len() {
+            return Err(GlusterError::new(format!(
+                "count {} is larger than the buffer of {} bytes",
+                count,
+                buffer.len()
+            )));
+        }
         unsafe {
             let write_size = glfs_pwrite(
                 self.file_handle,
@@ -1112,7 +1971,7 @@ impl GlusterFile {
                 std::ptr::null_mut()
             );
             if write_size < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
             Ok(write_size)
         }
@@ -1120,7 +1979,7 @@ impl GlusterFile {
 
     pub fn preadv(
         &self,
-        iov: &mut [&mut [u8]],
+        iov: &mut [IoSliceMut<'_>],
         offset: i64,
         flags: i32,
     ) -> Result<isize, GlusterError> {
@@ -1128,28 +1987,32 @@ impl GlusterFile {
             let read_size = glfs_preadv(
                 self.file_handle,
                 iov.as_ptr() as *const iovec,
-                iov.len() as i32,
+                iov_count(iov.len()),
                 offset,
                 flags,
             );
             if read_size < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
             Ok(read_size)
         }
     }
-    // TODO: Use C IoVec
-    pub fn pwritev(&self, iov: &[&[u8]], offset: i64, flags: i32) -> Result<isize, GlusterError> {
+    pub fn pwritev(
+        &self,
+        iov: &[IoSlice<'_>],
+        offset: i64,
+        flags: i32,
+    ) -> Result<isize, GlusterError> {
         unsafe {
             let write_size = glfs_pwritev(
                 self.file_handle,
                 iov.as_ptr() as *const iovec,
-                iov.len() as i32,
+                iov_count(iov.len()),
                 offset,
                 flags,
             );
             if write_size < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
             Ok(write_size)
         }
@@ -1158,7 +2021,7 @@ impl GlusterFile {
         unsafe {
             let file_offset = glfs_lseek(self.file_handle, offset, whence);
             if file_offset < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
             Ok(file_offset)
         }
@@ -1167,7 +2030,7 @@ impl GlusterFile {
         unsafe {
             let ret_code = glfs_ftruncate(self.file_handle, length, std::ptr::null_mut(), std::ptr::null_mut());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -1177,7 +2040,7 @@ impl GlusterFile {
             let mut stat_buf: stat = zeroed();
             let ret_code = glfs_fstat(self.file_handle, &mut stat_buf);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
             Ok(stat_buf)
         }
@@ -1186,7 +2049,7 @@ impl GlusterFile {
         unsafe {
             let ret_code = glfs_fsync(self.file_handle, std::ptr::null_mut(), std::ptr::null_mut());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -1196,45 +2059,26 @@ impl GlusterFile {
         unsafe {
             let ret_code = glfs_fdatasync(self.file_handle, std::ptr::null_mut(), std::ptr::null_mut());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
     }
     pub fn fgetxattr(&self, name: &str) -> Result<String, GlusterError> {
         let name = CString::new(name)?;
-        let mut xattr_val_buff: Vec<u8> = Vec::with_capacity(1024);
-        unsafe {
-            let ret_code = glfs_fgetxattr(
-                self.file_handle,
-                name.as_ptr(),
-                xattr_val_buff.as_mut_ptr() as *mut c_void,
-                xattr_val_buff.len(),
-            );
-            if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
-            }
-            // Set the buffer to the size of bytes read into it
-            xattr_val_buff.set_len(ret_code as usize);
-            Ok(String::from_utf8_lossy(&xattr_val_buff).into_owned())
-        }
+        let value = sized_fetch(
+            |buf, size| unsafe { glfs_fgetxattr(self.file_handle, name.as_ptr(), buf, size) },
+            get_error,
+        )?;
+        Ok(String::from_utf8_lossy(&value).into_owned())
     }
 
     pub fn flistxattr(&self) -> Result<String, GlusterError> {
-        let mut xattr_val_buff: Vec<u8> = Vec::with_capacity(1024);
-        unsafe {
-            let ret_code = glfs_flistxattr(
-                self.file_handle,
-                xattr_val_buff.as_mut_ptr() as *mut c_void,
-                xattr_val_buff.len(),
-            );
-            if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
-            }
-            // Set the buffer to the size of bytes read into it
-            xattr_val_buff.set_len(ret_code as usize);
-            Ok(String::from_utf8_lossy(&xattr_val_buff).into_owned())
-        }
+        let list = sized_fetch(
+            |buf, size| unsafe { glfs_flistxattr(self.file_handle, buf, size) },
+            get_error,
+        )?;
+        Ok(String::from_utf8_lossy(&list).into_owned())
     }
 
     pub fn fsetxattr(&self, name: &str, value: &[u8], flags: i32) -> Result<(), GlusterError> {
@@ -1248,7 +2092,7 @@ impl GlusterFile {
                 flags,
             );
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -1259,7 +2103,7 @@ impl GlusterFile {
         unsafe {
             let ret_code = glfs_fremovexattr(self.file_handle, name.as_ptr());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -1268,7 +2112,7 @@ impl GlusterFile {
         unsafe {
             let ret_code = glfs_fallocate(self.file_handle, keep_size, offset, len);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -1277,7 +2121,7 @@ impl GlusterFile {
         unsafe {
             let ret_code = glfs_discard(self.file_handle, offset, len);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -1286,7 +2130,7 @@ impl GlusterFile {
         unsafe {
             let ret_code = glfs_zerofill(self.file_handle, offset, len);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -1296,7 +2140,7 @@ impl GlusterFile {
         unsafe {
             let ret_code = glfs_fchdir(self.file_handle);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -1307,7 +2151,7 @@ impl GlusterFile {
         unsafe {
             let ret_code = glfs_futimens(self.file_handle, times.as_ptr());
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -1317,7 +2161,7 @@ impl GlusterFile {
         unsafe {
             let ret_code = glfs_posix_lock(self.file_handle, command.into(), flock);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -1326,7 +2170,7 @@ impl GlusterFile {
         unsafe {
             let ret_code = glfs_fchmod(self.file_handle, mode);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -1335,7 +2179,7 @@ impl GlusterFile {
         unsafe {
             let ret_code = glfs_fchown(self.file_handle, uid, gid);
             if ret_code < 0 {
-                return Err(GlusterError::new(get_error()));
+                return Err(get_error());
             }
         }
         Ok(())
@@ -1355,7 +2199,7 @@ impl GlusterFile {
     pub fn dup(&self) -> Result<GlusterFile, GlusterError> {
         unsafe {
             let file_handle = glfs_dup(self.file_handle);
-            Ok(GlusterFile { file_handle })
+            Ok(GlusterFile::from_raw(file_handle))
         }
     }
 }
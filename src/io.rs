@@ -0,0 +1,253 @@
+//! std::io traits for GlusterFile.
+//! Reads and writes go through glfs_read and glfs_write so they use and
+//! advance the file offset the same way a std::fs::File does.  They are
+//! implemented for &GlusterFile as well so a shared handle can be read from.
+//! GlusterFileExt adds positional I/O for handles shared between threads.
+//! gfapi reports errors through errno which is turned into an io::Error.
+//! IoSlice and IoSliceMut are guaranteed to be ABI compatible with iovec on
+//! unix so slices of them are passed to gluster as they are.
+use crate::glfs::*;
+use crate::gluster::GlusterFile;
+use libc::{c_int, c_void, SEEK_CUR, SEEK_END, SEEK_SET};
+
+use std::convert::TryFrom;
+use std::io::{self, IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
+
+impl Read for &GlusterFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_size = unsafe {
+            glfs_read(
+                self.file_handle,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if read_size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(read_size as usize)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        let read_size = unsafe {
+            glfs_readv(
+                self.file_handle,
+                bufs.as_ptr() as *const iovec,
+                iov_count(bufs.len()),
+                0,
+            )
+        };
+        if read_size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(read_size as usize)
+    }
+}
+
+impl Write for &GlusterFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let write_size = unsafe {
+            glfs_write(
+                self.file_handle,
+                buf.as_ptr() as *const c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if write_size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(write_size as usize)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let write_size = unsafe {
+            glfs_writev(
+                self.file_handle,
+                bufs.as_ptr() as *const iovec,
+                iov_count(bufs.len()),
+                0,
+            )
+        };
+        if write_size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(write_size as usize)
+    }
+
+    /// Writes aren't buffered on this side so there is nothing to flush.
+    /// Use fsync to make sure data has reached the bricks.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for &GlusterFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(offset) => (to_offset(offset)?, SEEK_SET),
+            SeekFrom::Current(offset) => (offset, SEEK_CUR),
+            SeekFrom::End(offset) => (offset, SEEK_END),
+        };
+        let file_offset = unsafe { glfs_lseek(self.file_handle, offset, whence) };
+        if file_offset < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(file_offset as u64)
+    }
+}
+
+// GlusterFile has inherent read and write methods so the trait methods
+// are called explicitly
+impl Read for GlusterFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Read::read(&mut &*self, buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        Read::read_vectored(&mut &*self, bufs)
+    }
+}
+
+impl Write for GlusterFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Write::write(&mut &*self, buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        Write::write_vectored(&mut &*self, bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Write::flush(&mut &*self)
+    }
+}
+
+impl Seek for GlusterFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        Seek::seek(&mut &*self, pos)
+    }
+}
+
+/// Positional reads and writes, like std::os::unix::fs::FileExt.  They
+/// don't use or move the file offset so threads sharing a GlusterFile
+/// don't race on seeks.
+pub trait GlusterFileExt {
+    /// Read into buf at offset and return the number of bytes read
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// Write buf at offset and return the number of bytes written
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize>;
+
+    /// Read into several buffers in turn starting at offset
+    fn read_vectored_at(&self, bufs: &mut [IoSliceMut<'_>], offset: u64) -> io::Result<usize>;
+
+    /// Write several buffers in turn starting at offset
+    fn write_vectored_at(&self, bufs: &[IoSlice<'_>], offset: u64) -> io::Result<usize>;
+
+    /// Fill buf from offset.  Reaching the end of the file first is an
+    /// UnexpectedEof error.
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    ));
+                }
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Write all of buf at offset, retrying short writes
+    fn write_all_at(&self, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.write_at(buf, offset) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ));
+                }
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Offsets are u64 in Rust and off_t in gfapi
+pub(crate) fn to_offset(offset: u64) -> io::Result<i64> {
+    i64::try_from(offset).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("offset {} is too large", offset),
+        )
+    })
+}
+
+/// Number of buffers to hand to gluster in one call
+pub(crate) fn iov_count(count: usize) -> c_int {
+    count.min(c_int::MAX as usize) as c_int
+}
+
+// The positional reads and writes are inherent methods of GlusterFile
+// returning GlusterError.  read_exact_at and write_all_at only exist here
+// so that they always return io::Error.
+impl GlusterFileExt for GlusterFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        Ok(GlusterFile::read_at(self, buf, offset)?)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        Ok(GlusterFile::write_at(self, buf, offset)?)
+    }
+
+    fn read_vectored_at(&self, bufs: &mut [IoSliceMut<'_>], offset: u64) -> io::Result<usize> {
+        let offset = to_offset(offset)?;
+        let read_size = unsafe {
+            glfs_preadv(
+                self.file_handle,
+                bufs.as_ptr() as *const iovec,
+                iov_count(bufs.len()),
+                offset,
+                0,
+            )
+        };
+        if read_size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(read_size as usize)
+    }
+
+    fn write_vectored_at(&self, bufs: &[IoSlice<'_>], offset: u64) -> io::Result<usize> {
+        let offset = to_offset(offset)?;
+        let write_size = unsafe {
+            glfs_pwritev(
+                self.file_handle,
+                bufs.as_ptr() as *const iovec,
+                iov_count(bufs.len()),
+                offset,
+                0,
+            )
+        };
+        if write_size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(write_size as usize)
+    }
+}
@@ -7,5 +7,21 @@
 #[macro_use]
 extern crate log;
 
+pub mod aio;
+pub mod async_gluster;
+pub mod copy;
+pub mod credentials;
 pub mod glfs;
 pub mod gluster;
+pub mod io;
+pub mod logging;
+pub mod object;
+pub mod registry;
+pub mod sparse;
+pub mod statedump;
+pub mod supervisor;
+#[cfg(feature = "tokio")]
+pub mod tokio_file;
+pub mod transfer;
+pub mod upcall;
+pub mod volfile;
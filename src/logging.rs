@@ -0,0 +1,243 @@
+//! Forward gfapi log messages to the log crate.
+//! gfapi can only log to a file so it is pointed at a FIFO and a reader
+//! thread turns each line into a log record.  The target of a record is
+//! gfapi::<xlator> and the file and line are those of the gluster source.
+use crate::glfs::*;
+use crate::gluster::{get_error, GlusterError, GlusterLogLevel};
+
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Error};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+impl GlusterLogLevel {
+    /// The log crate level for this gluster level.  None has no equivalent.
+    pub fn to_log_level(&self) -> Option<log::Level> {
+        match *self {
+            GlusterLogLevel::None => None,
+            GlusterLogLevel::Emerg
+            | GlusterLogLevel::Alert
+            | GlusterLogLevel::Critical
+            | GlusterLogLevel::Error => Some(log::Level::Error),
+            GlusterLogLevel::Warning => Some(log::Level::Warn),
+            GlusterLogLevel::Notice | GlusterLogLevel::Info => Some(log::Level::Info),
+            GlusterLogLevel::Debug => Some(log::Level::Debug),
+            GlusterLogLevel::Trace => Some(log::Level::Trace),
+        }
+    }
+
+    /// Parse the single letter gluster writes in its log lines
+    fn from_letter(letter: &str) -> Option<GlusterLogLevel> {
+        match letter {
+            "M" => Some(GlusterLogLevel::Emerg),
+            "A" => Some(GlusterLogLevel::Alert),
+            "C" => Some(GlusterLogLevel::Critical),
+            "E" => Some(GlusterLogLevel::Error),
+            "W" => Some(GlusterLogLevel::Warning),
+            "N" => Some(GlusterLogLevel::Notice),
+            "I" => Some(GlusterLogLevel::Info),
+            "D" => Some(GlusterLogLevel::Debug),
+            "T" => Some(GlusterLogLevel::Trace),
+            _ => None,
+        }
+    }
+}
+
+/// One line of a gluster log:
+///
+/// ```text
+/// [2020-06-02 10:15:42.123456 +0000] I [MSGID: 101190] [event-epoll.c:670:event_dispatch_epoll_worker] 0-epoll: Started thread with index 1
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct LogLine {
+    pub timestamp: String,
+    pub level: GlusterLogLevel,
+    pub msgid: Option<u32>,
+    pub file: String,
+    pub line: u32,
+    pub function: String,
+    /// Name of the translator that logged the message.  Empty if the
+    /// message didn't come from a translator.
+    pub xlator: String,
+    pub message: String,
+}
+
+/// Split "[inside] rest" into its two parts
+fn bracketed(s: &str) -> Option<(&str, &str)> {
+    let s = s.strip_prefix('[')?;
+    let end = s.find(']')?;
+    Some((&s[..end], s[end + 1..].trim_start()))
+}
+
+impl LogLine {
+    /// Parse a log line.  None is returned for lines that aren't in the
+    /// gluster format such as the continuation of a multi line message.
+    pub fn parse(line: &str) -> Option<LogLine> {
+        let (timestamp, rest) = bracketed(line)?;
+        let (letter, rest) = rest.split_once(' ')?;
+        let level = GlusterLogLevel::from_letter(letter)?;
+        let (mut location, mut rest) = bracketed(rest)?;
+        let mut msgid = None;
+        if let Some(id) = location.strip_prefix("MSGID:") {
+            msgid = id.trim().parse().ok();
+            let next = bracketed(rest)?;
+            location = next.0;
+            rest = next.1;
+        }
+        let mut location = location.splitn(3, ':');
+        let file = location.next()?.to_string();
+        let line_no = location.next()?.parse().ok()?;
+        let function = location.next()?.to_string();
+        // Messages are prefixed with <graph id>-<xlator>:
+        let (xlator, message) = match rest.split_once(": ") {
+            Some((prefix, message)) if !prefix.contains(' ') => {
+                let xlator = match prefix.split_once('-') {
+                    Some((id, name)) if id.chars().all(|c| c.is_ascii_digit()) => name,
+                    _ => prefix,
+                };
+                (xlator.to_string(), message.to_string())
+            }
+            _ => (String::new(), rest.to_string()),
+        };
+        Some(LogLine {
+            timestamp: timestamp.to_string(),
+            level,
+            msgid,
+            file,
+            line: line_no,
+            function,
+            xlator,
+            message,
+        })
+    }
+
+    fn target(&self) -> String {
+        if self.xlator.is_empty() {
+            "gfapi".to_string()
+        } else {
+            format!("gfapi::{}", self.xlator)
+        }
+    }
+}
+
+fn emit(level: log::Level, target: &str, file: Option<&str>, line: Option<u32>, msg: &str) {
+    let logger = log::logger();
+    let metadata = log::Metadata::builder().level(level).target(target).build();
+    if level > log::max_level() || !logger.enabled(&metadata) {
+        return;
+    }
+    logger.log(
+        &log::Record::builder()
+            .metadata(metadata)
+            .file(file)
+            .line(line)
+            .args(format_args!("{}", msg))
+            .build(),
+    );
+}
+
+/// Read gluster log lines until every writer has closed the FIFO
+fn forward(fifo: File) {
+    let reader = BufReader::new(fifo);
+    // Continuation lines are logged like the line they belong to
+    let mut last_level = log::Level::Info;
+    let mut last_target = "gfapi".to_string();
+    for line in reader.split(b'\n') {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Reading gfapi log failed: {:?}", e);
+                return;
+            }
+        };
+        let line = String::from_utf8_lossy(&line);
+        if line.trim().is_empty() {
+            continue;
+        }
+        match LogLine::parse(&line) {
+            Some(parsed) => {
+                last_level = parsed.level.to_log_level().unwrap_or(log::Level::Info);
+                last_target = parsed.target();
+                let msg = match parsed.msgid {
+                    Some(id) => format!("[MSGID: {}] {}: {}", id, parsed.function, parsed.message),
+                    None => format!("{}: {}", parsed.function, parsed.message),
+                };
+                emit(
+                    last_level,
+                    &last_target,
+                    Some(&parsed.file),
+                    Some(parsed.line),
+                    &msg,
+                );
+            }
+            None => emit(last_level, &last_target, None, None, &line),
+        }
+    }
+}
+
+/// Send the log messages of cluster_handle to the log crate instead of a
+/// file.  Messages at or above loglevel are forwarded.  A thread named
+/// gfapi-log reads them until glfs_fini closes the log.
+/// This runs before glfs_init so that messages about fetching the volfile
+/// and connecting to the bricks are forwarded too.
+pub(crate) fn forward_logging(
+    cluster_handle: *mut glfs,
+    loglevel: GlusterLogLevel,
+) -> Result<(), GlusterError> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "gfapi-log-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    unsafe {
+        if libc::mkfifo(c_path.as_ptr(), 0o600) < 0 {
+            return Err(get_error());
+        }
+    }
+    // Opening the read side without blocking means gluster's open for
+    // writing succeeds straight away.  Blocking is turned back on so
+    // the reader thread sleeps until there is something to read.
+    let fifo = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&path);
+    let result = fifo.and_then(|fifo| {
+        unsafe {
+            let flags = libc::fcntl(fifo.as_raw_fd(), libc::F_GETFL);
+            if flags < 0
+                || libc::fcntl(fifo.as_raw_fd(), libc::F_SETFL, flags & !libc::O_NONBLOCK) < 0
+            {
+                return Err(Error::last_os_error());
+            }
+        }
+        Ok(fifo)
+    });
+    let fifo = match result {
+        Ok(fifo) => fifo,
+        Err(e) => {
+            let _ = fs::remove_file(&path);
+            return Err(e.into());
+        }
+    };
+    let ret_code = unsafe { glfs_set_logging(cluster_handle, c_path.as_ptr(), loglevel as i32) };
+    let set_logging = if ret_code < 0 {
+        Err(get_error())
+    } else {
+        Ok(())
+    };
+    // Both ends are open now so the name isn't needed any more
+    if let Err(e) = fs::remove_file(&path) {
+        error!("Unable to remove {}: {:?}", path.display(), e);
+    }
+    set_logging?;
+    thread::Builder::new()
+        .name("gfapi-log".to_string())
+        .spawn(move || forward(fifo))?;
+    Ok(())
+}
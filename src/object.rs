@@ -0,0 +1,490 @@
+//! Handle based access to files and directories.
+//! A GlusterObject refers to an inode directly.  Operations on it don't
+//! resolve a path from the root of the volume every time the way the path
+//! based calls in the gluster module do, and they keep working on the same
+//! inode when another client renames it.
+use crate::glfs::*;
+use crate::gluster::{get_error, steps, Gluster, GlusterError, GlusterFile, Step, MAX_SYMLINKS};
+use libc::{c_char, gid_t, mode_t, off_t, stat, timespec, uid_t, ELOOP, PATH_MAX, S_IFLNK, S_IFMT};
+
+use std::ffi::{CStr, CString, OsString};
+use std::io::Error;
+use std::mem::zeroed;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Component, Path, PathBuf};
+use std::ptr;
+use uuid::Uuid;
+
+/// Length in bytes of an object handle.  A handle is the GFID of the inode.
+pub const GFAPI_HANDLE_LENGTH: usize = 16;
+
+const GFAPI_SET_ATTR_MODE: i32 = 0x1;
+const GFAPI_SET_ATTR_UID: i32 = 0x2;
+const GFAPI_SET_ATTR_GID: i32 = 0x4;
+const GFAPI_SET_ATTR_SIZE: i32 = 0x8;
+const GFAPI_SET_ATTR_ATIME: i32 = 0x10;
+const GFAPI_SET_ATTR_MTIME: i32 = 0x20;
+
+/// Attributes to change with GlusterObject::setattrs.  Only the fields that
+/// are set are changed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SetAttrs {
+    pub mode: Option<mode_t>,
+    pub uid: Option<uid_t>,
+    pub gid: Option<gid_t>,
+    pub size: Option<off_t>,
+    pub atime: Option<timespec>,
+    pub mtime: Option<timespec>,
+}
+
+/// A handle to an inode on the volume.  Released with glfs_h_close on drop.
+#[derive(Debug)]
+pub struct GlusterObject<'a> {
+    gluster: &'a Gluster,
+    pub(crate) object: *mut glfs_object,
+}
+
+// Object handles are reference counted inodes inside gfapi and can be
+// used from any thread
+unsafe impl<'a> Send for GlusterObject<'a> {}
+unsafe impl<'a> Sync for GlusterObject<'a> {}
+
+impl<'a> Drop for GlusterObject<'a> {
+    fn drop(&mut self) {
+        if self.object.is_null() {
+            // No cleanup needed
+            return;
+        }
+        unsafe {
+            let retcode = glfs_h_close(self.object);
+            if retcode < 0 {
+                error!("{:?}", get_error());
+            }
+        }
+    }
+}
+
+/// Copy the GFID out of an object handle
+pub(crate) fn object_gfid(object: *mut glfs_object) -> Result<Uuid, GlusterError> {
+    let mut handle = [0u8; GFAPI_HANDLE_LENGTH];
+    unsafe {
+        let ret_code = glfs_h_extract_handle(
+            object,
+            handle.as_mut_ptr(),
+            GFAPI_HANDLE_LENGTH as ::libc::c_int,
+        );
+        if ret_code < 0 {
+            return Err(get_error());
+        }
+    }
+    Ok(Uuid::from_bytes(handle))
+}
+
+impl Gluster {
+    /// Reopen an object from the GFID returned by GlusterObject::gfid.
+    /// GFIDs don't change for the life of an inode so this works across
+    /// process restarts.  On a subdirectory mount the object has to be
+    /// inside the subdirectory, which is checked like gfid_to_path does
+    /// and for files needs the storage.build-pgfid volume option.
+    pub fn object_from_gfid(&self, gfid: &Uuid) -> Result<GlusterObject<'_>, GlusterError> {
+        self.check_gfid_beneath(gfid)?;
+        let mut handle = *gfid.as_bytes();
+        unsafe {
+            let object = glfs_h_create_from_handle(
+                self.cluster_handle,
+                handle.as_mut_ptr(),
+                GFAPI_HANDLE_LENGTH as ::libc::c_int,
+                ptr::null_mut(),
+            );
+            if object.is_null() {
+                return Err(get_error());
+            }
+            Ok(GlusterObject::from_raw(self, object))
+        }
+    }
+
+    /// Look up path and return a handle to it.  If follow is true and path
+    /// is a symlink the handle refers to the target.
+    pub fn lookup_object(
+        &self,
+        path: &Path,
+        follow: bool,
+    ) -> Result<GlusterObject<'_>, GlusterError> {
+        let path = if follow {
+            self.to_cpath(path)?
+        } else {
+            self.to_cpath_nofollow(path)?
+        };
+        unsafe {
+            let object = glfs_h_lookupat(
+                self.cluster_handle,
+                ptr::null_mut(),
+                path.as_ptr(),
+                ptr::null_mut(),
+                follow as i32,
+            );
+            if object.is_null() {
+                return Err(get_error());
+            }
+            Ok(GlusterObject {
+                gluster: self,
+                object,
+            })
+        }
+    }
+
+    /// A handle to the root of the connection.  For a subdirectory mount
+    /// this is the subdirectory.
+    pub fn root_object(&self) -> Result<GlusterObject<'_>, GlusterError> {
+        self.lookup_object(Path::new("/"), true)
+    }
+}
+
+impl<'a> GlusterObject<'a> {
+    pub(crate) fn from_raw(gluster: &'a Gluster, object: *mut glfs_object) -> GlusterObject<'a> {
+        GlusterObject { gluster, object }
+    }
+
+    /// The GFID of this object.  It identifies the inode for as long as it
+    /// exists and can be turned back into an object with
+    /// Gluster::object_from_gfid.
+    pub fn gfid(&self) -> Result<Uuid, GlusterError> {
+        object_gfid(self.object)
+    }
+
+    /// The connection this object belongs to
+    pub fn gluster(&self) -> &'a Gluster {
+        self.gluster
+    }
+
+    /// Convert a name inside this directory.  On a subdirectory mount it
+    /// has to be a single plain name.  gfapi resolves "..", a leading "/"
+    /// and symlinks in the directories on the way itself, any of which
+    /// can lead out of the subdirectory.
+    fn to_cname(&self, name: &Path) -> Result<CString, GlusterError> {
+        if self.gluster.subdir().is_some() {
+            let mut components = name.components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) => {}
+                _ => {
+                    return Err(GlusterError::new(format!(
+                        "{} is not a plain name inside the subdirectory mount",
+                        name.display()
+                    )));
+                }
+            }
+        }
+        Ok(CString::new(name.as_os_str().as_bytes())?)
+    }
+
+    fn same_volume(&self, other: &GlusterObject<'_>) -> Result<(), GlusterError> {
+        if !ptr::eq(self.gluster, other.gluster) {
+            return Err(GlusterError::new(
+                "objects belong to different connections".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Look up name inside this directory.  name may have several
+    /// components.  If follow is true and name is a symlink the handle
+    /// refers to the target.
+    pub fn lookupat(&self, name: &Path, follow: bool) -> Result<GlusterObject<'a>, GlusterError> {
+        if self.gluster.subdir().is_some() {
+            return self.lookupat_beneath(name, follow);
+        }
+        let name = CString::new(name.as_os_str().as_bytes())?;
+        self.lookup_raw(&name, follow, ptr::null_mut())
+    }
+
+    fn lookup_raw(
+        &self,
+        name: &CStr,
+        follow: bool,
+        stat_buf: *mut stat,
+    ) -> Result<GlusterObject<'a>, GlusterError> {
+        unsafe {
+            let object = glfs_h_lookupat(
+                self.gluster.cluster_handle,
+                self.object,
+                name.as_ptr(),
+                stat_buf,
+                follow as i32,
+            );
+            if object.is_null() {
+                return Err(get_error());
+            }
+            Ok(GlusterObject::from_raw(self.gluster, object))
+        }
+    }
+
+    /// lookupat on a subdirectory mount.  gfapi would follow symlinks on
+    /// the way itself, absolute ones from the root of the volume, so name
+    /// is walked one component at a time and symlinks are resolved here
+    /// with absolute targets starting at the subdirectory.  ".." may not
+    /// climb above the subdirectory.
+    fn lookupat_beneath(
+        &self,
+        name: &Path,
+        follow: bool,
+    ) -> Result<GlusterObject<'a>, GlusterError> {
+        let root_gfid = self.gluster.root_object()?.gfid()?;
+        let escapes =
+            || GlusterError::new(format!("{} escapes the subdirectory mount", name.display()));
+        // The directory reached so far, None while it is still self
+        let mut current: Option<GlusterObject<'a>> = None;
+        let mut pending: Vec<Step> = steps(name).rev().collect();
+        let mut links = 0;
+        while let Some(step) = pending.pop() {
+            let dir = current.as_ref().unwrap_or(self);
+            match step {
+                Step::Root => current = Some(self.gluster.root_object()?),
+                Step::Parent => {
+                    if dir.gfid()? == root_gfid {
+                        return Err(escapes());
+                    }
+                    current = Some(dir.lookup_raw(&CString::new("..")?, false, ptr::null_mut())?);
+                }
+                Step::Name(component) => {
+                    let cname = CString::new(component.into_vec())?;
+                    let mut stat_buf: stat = unsafe { zeroed() };
+                    let object = dir.lookup_raw(&cname, false, &mut stat_buf)?;
+                    let last = pending.is_empty();
+                    if (last && !follow) || stat_buf.st_mode & S_IFMT != S_IFLNK {
+                        current = Some(object);
+                        continue;
+                    }
+                    links += 1;
+                    if links > MAX_SYMLINKS {
+                        return Err(Error::from_raw_os_error(ELOOP).into());
+                    }
+                    // Carry on from the same directory with the target in
+                    // place of the symlink
+                    pending.extend(steps(&object.readlink()?).rev());
+                }
+            }
+        }
+        match current {
+            Some(object) => Ok(object),
+            // name was empty or "."
+            None => self.lookup_raw(&CString::new(".")?, false, ptr::null_mut()),
+        }
+    }
+
+    /// Create a file called name inside this directory
+    pub fn creat(
+        &self,
+        name: &Path,
+        flags: i32,
+        mode: mode_t,
+    ) -> Result<GlusterObject<'a>, GlusterError> {
+        let name = self.to_cname(name)?;
+        unsafe {
+            let object = glfs_h_creat(
+                self.gluster.cluster_handle,
+                self.object,
+                name.as_ptr(),
+                flags,
+                mode,
+                ptr::null_mut(),
+            );
+            if object.is_null() {
+                return Err(get_error());
+            }
+            Ok(GlusterObject::from_raw(self.gluster, object))
+        }
+    }
+
+    /// Create a directory called name inside this directory
+    pub fn mkdir(&self, name: &Path, mode: mode_t) -> Result<GlusterObject<'a>, GlusterError> {
+        let name = self.to_cname(name)?;
+        unsafe {
+            let object = glfs_h_mkdir(
+                self.gluster.cluster_handle,
+                self.object,
+                name.as_ptr(),
+                mode,
+                ptr::null_mut(),
+            );
+            if object.is_null() {
+                return Err(get_error());
+            }
+            Ok(GlusterObject::from_raw(self.gluster, object))
+        }
+    }
+
+    /// Create a symlink called name inside this directory that points
+    /// to target
+    pub fn symlink(&self, name: &Path, target: &Path) -> Result<GlusterObject<'a>, GlusterError> {
+        let name = self.to_cname(name)?;
+        let target = CString::new(target.as_os_str().as_bytes())?;
+        unsafe {
+            let object = glfs_h_symlink(
+                self.gluster.cluster_handle,
+                self.object,
+                name.as_ptr(),
+                target.as_ptr(),
+                ptr::null_mut(),
+            );
+            if object.is_null() {
+                return Err(get_error());
+            }
+            Ok(GlusterObject::from_raw(self.gluster, object))
+        }
+    }
+
+    /// Open the file this object refers to
+    pub fn open(&self, flags: i32) -> Result<GlusterFile, GlusterError> {
+        unsafe {
+            let file_handle = glfs_h_open(self.gluster.cluster_handle, self.object, flags);
+            if file_handle.is_null() {
+                return Err(get_error());
+            }
+            Ok(GlusterFile::from_raw(file_handle))
+        }
+    }
+
+    /// Stat the object, refreshing the attributes from the bricks
+    pub fn stat(&self) -> Result<stat, GlusterError> {
+        unsafe {
+            let mut stat_buf: stat = zeroed();
+            let ret_code = glfs_h_stat(self.gluster.cluster_handle, self.object, &mut stat_buf);
+            if ret_code < 0 {
+                return Err(get_error());
+            }
+            Ok(stat_buf)
+        }
+    }
+
+    /// The attributes of the object
+    pub fn getattrs(&self) -> Result<stat, GlusterError> {
+        unsafe {
+            let mut stat_buf: stat = zeroed();
+            let ret_code = glfs_h_getattrs(self.gluster.cluster_handle, self.object, &mut stat_buf);
+            if ret_code < 0 {
+                return Err(get_error());
+            }
+            Ok(stat_buf)
+        }
+    }
+
+    pub fn setattrs(&self, attrs: &SetAttrs) -> Result<(), GlusterError> {
+        let mut valid = 0;
+        unsafe {
+            let mut stat_buf: stat = zeroed();
+            if let Some(mode) = attrs.mode {
+                stat_buf.st_mode = mode;
+                valid |= GFAPI_SET_ATTR_MODE;
+            }
+            if let Some(uid) = attrs.uid {
+                stat_buf.st_uid = uid;
+                valid |= GFAPI_SET_ATTR_UID;
+            }
+            if let Some(gid) = attrs.gid {
+                stat_buf.st_gid = gid;
+                valid |= GFAPI_SET_ATTR_GID;
+            }
+            if let Some(size) = attrs.size {
+                stat_buf.st_size = size;
+                valid |= GFAPI_SET_ATTR_SIZE;
+            }
+            if let Some(atime) = attrs.atime {
+                stat_buf.st_atime = atime.tv_sec;
+                stat_buf.st_atime_nsec = atime.tv_nsec;
+                valid |= GFAPI_SET_ATTR_ATIME;
+            }
+            if let Some(mtime) = attrs.mtime {
+                stat_buf.st_mtime = mtime.tv_sec;
+                stat_buf.st_mtime_nsec = mtime.tv_nsec;
+                valid |= GFAPI_SET_ATTR_MTIME;
+            }
+            let ret_code = glfs_h_setattrs(
+                self.gluster.cluster_handle,
+                self.object,
+                &mut stat_buf,
+                valid,
+            );
+            if ret_code < 0 {
+                return Err(get_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove name from this directory
+    pub fn unlink(&self, name: &Path) -> Result<(), GlusterError> {
+        let name = self.to_cname(name)?;
+        unsafe {
+            let ret_code = glfs_h_unlink(self.gluster.cluster_handle, self.object, name.as_ptr());
+            if ret_code < 0 {
+                return Err(get_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Rename oldname in this directory to newname in newdir
+    pub fn rename(
+        &self,
+        oldname: &Path,
+        newdir: &GlusterObject<'_>,
+        newname: &Path,
+    ) -> Result<(), GlusterError> {
+        self.same_volume(newdir)?;
+        let oldname = self.to_cname(oldname)?;
+        let newname = self.to_cname(newname)?;
+        unsafe {
+            let ret_code = glfs_h_rename(
+                self.gluster.cluster_handle,
+                self.object,
+                oldname.as_ptr(),
+                newdir.object,
+                newname.as_ptr(),
+            );
+            if ret_code < 0 {
+                return Err(get_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Create a hard link to this object called name in parent
+    pub fn link(&self, parent: &GlusterObject<'_>, name: &Path) -> Result<(), GlusterError> {
+        self.same_volume(parent)?;
+        let name = self.to_cname(name)?;
+        unsafe {
+            let ret_code = glfs_h_link(
+                self.gluster.cluster_handle,
+                self.object,
+                parent.object,
+                name.as_ptr(),
+            );
+            if ret_code < 0 {
+                return Err(get_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the target of a symlink
+    pub fn readlink(&self) -> Result<PathBuf, GlusterError> {
+        let mut buf: Vec<u8> = vec![0; PATH_MAX as usize];
+        unsafe {
+            let ret_code = glfs_h_readlink(
+                self.gluster.cluster_handle,
+                self.object,
+                buf.as_mut_ptr() as *mut c_char,
+                buf.len(),
+            );
+            if ret_code < 0 {
+                return Err(get_error());
+            }
+            buf.truncate(ret_code as usize);
+        }
+        // Some versions of gluster count the trailing nul
+        if buf.last() == Some(&0) {
+            buf.pop();
+        }
+        Ok(PathBuf::from(OsString::from_vec(buf)))
+    }
+}
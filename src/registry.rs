@@ -0,0 +1,209 @@
+//! A cache of Gluster connections shared across a process.
+//! Connections are opened the first time a volume is asked for and handed
+//! out as Arc handles.  Connections that nobody holds and that haven't been
+//! asked for within the idle TTL are finalized.
+use crate::gluster::{Gluster, GlusterBuilder, GlusterError, VolfileServer};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Identifies one connection in the registry
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VolumeKey {
+    pub servers: Vec<VolfileServer>,
+    pub volume: String,
+    pub subdir: Option<PathBuf>,
+}
+
+/// Make subdir absolute and drop repeated and trailing slashes so that
+/// one mount always has the same key
+fn normalize_subdir(subdir: &Path) -> PathBuf {
+    Path::new("/").join(subdir).components().collect()
+}
+
+impl VolumeKey {
+    /// volume may include a subdirectory like GlusterBuilder::new accepts.
+    /// "volume/sub" and volume "volume" with subdir "sub" are the same key.
+    pub fn new(servers: Vec<VolfileServer>, volume: &str) -> VolumeKey {
+        let (volume, subdir) = match volume.split_once('/') {
+            Some((volume, subdir)) => (volume, Some(normalize_subdir(Path::new(subdir)))),
+            None => (volume, None),
+        };
+        VolumeKey {
+            servers,
+            volume: volume.to_string(),
+            subdir,
+        }
+    }
+
+    pub fn subdir(mut self, subdir: PathBuf) -> VolumeKey {
+        self.subdir = Some(normalize_subdir(&subdir));
+        self
+    }
+
+    fn builder(&self) -> GlusterBuilder {
+        let builder = GlusterBuilder::new(&self.volume).servers(self.servers.iter().cloned());
+        match self.subdir {
+            Some(ref subdir) => builder.subdir(subdir),
+            None => builder,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    gluster: Option<Arc<Gluster>>,
+    last_used: Option<Instant>,
+    /// Set when reap_idle or clear takes the slot out of the map
+    removed: bool,
+}
+
+type Configure = Box<dyn Fn(GlusterBuilder) -> GlusterBuilder + Send + Sync>;
+
+pub struct GlusterRegistry {
+    idle_ttl: Duration,
+    // Each key has its own lock so that a slow connect to one volume
+    // doesn't hold up the others
+    slots: Mutex<HashMap<VolumeKey, Arc<Mutex<Slot>>>>,
+    configure: Option<Configure>,
+}
+
+impl GlusterRegistry {
+    /// Connections that are idle for longer than idle_ttl are finalized by
+    /// reap_idle
+    pub fn new(idle_ttl: Duration) -> GlusterRegistry {
+        GlusterRegistry {
+            idle_ttl,
+            slots: Mutex::new(HashMap::new()),
+            configure: None,
+        }
+    }
+
+    /// Adjust the builder used for every new connection, for example to add
+    /// xlator options
+    pub fn configure<F>(mut self, configure: F) -> GlusterRegistry
+    where
+        F: Fn(GlusterBuilder) -> GlusterBuilder + Send + Sync + 'static,
+    {
+        self.configure = Some(Box::new(configure));
+        self
+    }
+
+    /// Return the connection for key, connecting if there isn't one yet
+    pub fn get(&self, key: &VolumeKey) -> Result<Arc<Gluster>, GlusterError> {
+        loop {
+            let slot = {
+                let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+                Arc::clone(slots.entry(key.clone()).or_default())
+            };
+            let mut slot = slot.lock().unwrap_or_else(|e| e.into_inner());
+            // reap_idle or clear may have taken the slot out of the map
+            // before it was locked.  Connecting into it would leave the next get to open a
+            // second connection for key.
+            if slot.removed {
+                continue;
+            }
+            return self.connect_slot(key, &mut slot);
+        }
+    }
+
+    fn connect_slot(&self, key: &VolumeKey, slot: &mut Slot) -> Result<Arc<Gluster>, GlusterError> {
+        slot.last_used = Some(Instant::now());
+        if let Some(ref gluster) = slot.gluster {
+            return Ok(Arc::clone(gluster));
+        }
+        let builder = match self.configure {
+            Some(ref configure) => configure(key.builder()),
+            None => key.builder(),
+        };
+        let gluster = Arc::new(builder.connect()?);
+        slot.gluster = Some(Arc::clone(&gluster));
+        Ok(gluster)
+    }
+
+    /// Finalize connections that nobody outside the registry holds and that
+    /// haven't been asked for within the idle TTL.  Returns how many were
+    /// finalized.
+    pub fn reap_idle(&self) -> usize {
+        let mut idle = Vec::new();
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        slots.retain(|key, slot| {
+            // A slot that is busy connecting is in use
+            let mut slot = match slot.try_lock() {
+                Ok(slot) => slot,
+                Err(_) => return true,
+            };
+            let expired = match slot.last_used {
+                Some(used) => used.elapsed() >= self.idle_ttl,
+                None => true,
+            };
+            let unused = match slot.gluster {
+                Some(ref gluster) => Arc::strong_count(gluster) == 1,
+                None => true,
+            };
+            if expired && unused {
+                trace!("finalizing idle connection to {}", key.volume);
+                slot.removed = true;
+                if let Some(gluster) = slot.gluster.take() {
+                    idle.push(gluster);
+                }
+                return false;
+            }
+            true
+        });
+        drop(slots);
+        let count = idle.len();
+        // glfs_fini can be slow so it runs without holding the lock
+        drop(idle);
+        count
+    }
+
+    /// Drop every connection the registry holds.  Handles that are still
+    /// held elsewhere stay open until they are dropped.
+    pub fn clear(&self) {
+        let slots = {
+            let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+            std::mem::take(&mut *slots)
+        };
+        // A get that found a slot before it was taken must not connect into
+        // it.  Locking waits for a connect in progress to finish.
+        for slot in slots.values() {
+            let mut slot = slot.lock().unwrap_or_else(|e| e.into_inner());
+            slot.removed = true;
+            slot.gluster.take();
+        }
+    }
+
+    /// Number of volumes the registry has a slot for
+    pub fn len(&self) -> usize {
+        self.slots.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Call reap_idle every interval from a background thread.  The
+    /// thread exits once the registry is dropped.
+    pub fn spawn_reaper(
+        registry: &Arc<GlusterRegistry>,
+        interval: Duration,
+    ) -> Result<thread::JoinHandle<()>, GlusterError> {
+        let weak: Weak<GlusterRegistry> = Arc::downgrade(registry);
+        let handle = thread::Builder::new()
+            .name("gfapi-reaper".to_string())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                match weak.upgrade() {
+                    Some(registry) => {
+                        registry.reap_idle();
+                    }
+                    None => return,
+                }
+            })?;
+        Ok(handle)
+    }
+}
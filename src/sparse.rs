@@ -0,0 +1,124 @@
+//! Find the data and the holes of sparse files.
+//! glfs_lseek with SEEK_DATA and SEEK_HOLE asks the bricks where the data
+//! is so large sparse files such as VM images can be copied without
+//! reading their holes.  Volumes that can't answer, for example because a
+//! translator doesn't implement seek, report the whole file as data.
+use crate::gluster::{GlusterError, GlusterFile};
+use crate::io::to_offset;
+use libc::{ENXIO, SEEK_DATA, SEEK_HOLE};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExtentKind {
+    Data,
+    Hole,
+}
+
+/// A range of a file that is either all data or all hole
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Extent {
+    pub kind: ExtentKind,
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl Extent {
+    /// The offset just past the end of the extent
+    pub fn end(&self) -> u64 {
+        self.offset + self.len
+    }
+}
+
+/// Iterator over the extents of a file from start to end.  Returned by
+/// GlusterFile::extents.
+pub struct Extents<'a> {
+    file: &'a GlusterFile,
+    offset: u64,
+    size: u64,
+    /// Set once the volume turned out not to support SEEK_DATA/SEEK_HOLE
+    unsupported: bool,
+}
+
+impl<'a> Extents<'a> {
+    fn extent(&mut self, kind: ExtentKind, end: u64) -> Extent {
+        let end = end.min(self.size);
+        let extent = Extent {
+            kind,
+            offset: self.offset,
+            len: end - self.offset,
+        };
+        self.offset = end;
+        extent
+    }
+
+    fn seek(&self, whence: i32) -> Result<u64, GlusterError> {
+        let offset = to_offset(self.offset)?;
+        Ok(self.file.lseek(offset, whence)? as u64)
+    }
+
+    fn next_extent(&mut self) -> Result<Extent, GlusterError> {
+        if self.unsupported {
+            return Ok(self.extent(ExtentKind::Data, self.size));
+        }
+        let data = match self.seek(SEEK_DATA) {
+            Ok(data) => data,
+            // There is no data past offset
+            Err(ref e) if e.is_errno(ENXIO) => return Ok(self.extent(ExtentKind::Hole, self.size)),
+            Err(ref e) if e.is_unsupported() => {
+                debug!(
+                    "SEEK_DATA isn't supported, treating the file as data: {}",
+                    e
+                );
+                self.unsupported = true;
+                return Ok(self.extent(ExtentKind::Data, self.size));
+            }
+            Err(e) => return Err(e),
+        };
+        if data > self.offset {
+            return Ok(self.extent(ExtentKind::Hole, data));
+        }
+        match self.seek(SEEK_HOLE) {
+            Ok(hole) if hole > self.offset => Ok(self.extent(ExtentKind::Data, hole)),
+            // A hole at offset right after SEEK_DATA found data there
+            // means the answers can't be trusted
+            Ok(_) => {
+                self.unsupported = true;
+                Ok(self.extent(ExtentKind::Data, self.size))
+            }
+            Err(ref e) if e.is_unsupported() => {
+                self.unsupported = true;
+                Ok(self.extent(ExtentKind::Data, self.size))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<'a> Iterator for Extents<'a> {
+    type Item = Result<Extent, GlusterError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.size {
+            return None;
+        }
+        let extent = self.next_extent();
+        if extent.is_err() {
+            // Don't keep failing on the same offset
+            self.offset = self.size;
+        }
+        Some(extent)
+    }
+}
+
+impl GlusterFile {
+    /// Iterate over the data and hole extents of the file as of its size
+    /// now.  This moves the file offset.
+    pub fn extents(&self) -> Result<Extents<'_>, GlusterError> {
+        let size = self.fstat()?.st_size as u64;
+        Ok(Extents {
+            file: self,
+            offset: 0,
+            size,
+            unsupported: false,
+        })
+    }
+}
@@ -0,0 +1,321 @@
+//! Trigger and parse statedumps of the gfapi client.
+//! A statedump is a text file made of sections.  Each section starts with
+//! a [name] header followed by key=value lines.  Memory pools are listed
+//! inside a single [mempool] section separated by -----=----- lines.
+use crate::gluster::{Gluster, GlusterError, SysRqKey};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const RECORD_SEPARATOR: &str = "-----=-----";
+
+/// A [name] section of a statedump
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    /// key=value lines in the order they appear
+    pub entries: Vec<(String, String)>,
+    /// Sections such as [mempool] hold a list of records separated by
+    /// -----=----- lines.  Those records are kept here instead of entries.
+    pub records: Vec<Vec<(String, String)>>,
+}
+
+impl Section {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        lookup(&self.entries, key)
+    }
+
+    /// Parse a value as a number.  Missing or malformed values are 0.
+    pub fn get_u64(&self, key: &str) -> u64 {
+        lookup_u64(&self.entries, key)
+    }
+
+    /// Some sections prefix their keys with the section name, for example
+    /// xlator.mount.api.itable.active_size.  Look up key either way.
+    fn get_prefixed_u64(&self, key: &str) -> u64 {
+        match self.get(key) {
+            Some(_) => self.get_u64(key),
+            None => self.get_u64(&format!("{}.{}", self.name, key)),
+        }
+    }
+}
+
+fn lookup<'a>(entries: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    entries
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+fn lookup_u64(entries: &[(String, String)], key: &str) -> u64 {
+    lookup(entries, key)
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Look up key, or legacy when key is missing
+fn lookup_u64_or(entries: &[(String, String)], key: &str, legacy: &str) -> u64 {
+    match lookup(entries, key) {
+        Some(_) => lookup_u64(entries, key),
+        None => lookup_u64(entries, legacy),
+    }
+}
+
+/// Allocations of one memory type by one translator
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub xlator: String,
+    pub type_name: String,
+    pub size: u64,
+    pub num_allocs: u64,
+    pub max_size: u64,
+    pub max_num_allocs: u64,
+    pub total_allocs: u64,
+}
+
+/// One memory pool from the [mempool] section.  Current releases write
+/// active-count, sizeof-type, padded-sizeof, size and shared-pool.  Older
+/// ones wrote hot-count, cold-count, padded_sizeof, alloc-count, max-alloc
+/// and pool-misses instead.  Fields a dump doesn't have are 0.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryPool {
+    pub name: String,
+    /// Objects in use, hot-count in older dumps
+    pub active_count: u64,
+    pub sizeof_type: u64,
+    pub padded_sizeof: u64,
+    /// Bytes held by the objects in use
+    pub size: u64,
+    /// Address of the shared pool the objects come from
+    pub shared_pool: Option<String>,
+    pub hot_count: u64,
+    pub cold_count: u64,
+    pub alloc_count: u64,
+    pub max_alloc: u64,
+    pub pool_misses: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InodeTable {
+    /// The section prefix, for example xlator.mount.api.itable
+    pub name: String,
+    pub active_size: u64,
+    pub lru_size: u64,
+    pub purge_size: u64,
+    pub lru_limit: u64,
+}
+
+/// A call stack that was in flight when the dump was taken
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallStack {
+    pub stack: Section,
+    pub frames: Vec<Section>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallPool {
+    pub count: u64,
+    pub stacks: Vec<CallStack>,
+}
+
+/// A parsed statedump
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Statedump {
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub sections: Vec<Section>,
+}
+
+impl Statedump {
+    pub fn from_file(path: &Path) -> Result<Statedump, GlusterError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// Sections belonging to a translator such as
+    /// "xlator.protocol.client.test-client-0"
+    pub fn sections_with_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = &'a Section> {
+        self.sections
+            .iter()
+            .filter(move |s| s.name == prefix || s.name.starts_with(&format!("{}.", prefix)))
+    }
+
+    /// Memory accounting per translator and memory type.  These are the
+    /// "<xlator> - usage-type <type> memusage" sections.
+    pub fn memory_usage(&self) -> Vec<MemoryUsage> {
+        self.sections
+            .iter()
+            .filter_map(|s| {
+                let rest = s.name.strip_suffix(" memusage")?;
+                let (xlator, type_name) = rest.split_once(" - usage-type ")?;
+                Some(MemoryUsage {
+                    xlator: xlator.to_string(),
+                    type_name: type_name.to_string(),
+                    size: s.get_u64("size"),
+                    num_allocs: s.get_u64("num_allocs"),
+                    max_size: s.get_u64("max_size"),
+                    max_num_allocs: s.get_u64("max_num_allocs"),
+                    total_allocs: s.get_u64("total_allocs"),
+                })
+            })
+            .collect()
+    }
+
+    pub fn memory_pools(&self) -> Vec<MemoryPool> {
+        self.sections
+            .iter()
+            .filter(|s| s.name == "mempool")
+            .flat_map(|s| s.records.iter())
+            .filter_map(|r| {
+                Some(MemoryPool {
+                    name: lookup(r, "pool-name")?.to_string(),
+                    active_count: lookup_u64_or(r, "active-count", "hot-count"),
+                    sizeof_type: lookup_u64(r, "sizeof-type"),
+                    padded_sizeof: lookup_u64_or(r, "padded-sizeof", "padded_sizeof"),
+                    size: lookup_u64(r, "size"),
+                    shared_pool: lookup(r, "shared-pool").map(str::to_string),
+                    hot_count: lookup_u64(r, "hot-count"),
+                    cold_count: lookup_u64(r, "cold-count"),
+                    alloc_count: lookup_u64(r, "alloc-count"),
+                    max_alloc: lookup_u64(r, "max-alloc"),
+                    pool_misses: lookup_u64(r, "pool-misses"),
+                })
+            })
+            .collect()
+    }
+
+    pub fn inode_tables(&self) -> Vec<InodeTable> {
+        self.sections
+            .iter()
+            .filter(|s| s.name.ends_with(".itable"))
+            .map(|s| InodeTable {
+                name: s.name.clone(),
+                active_size: s.get_prefixed_u64("active_size"),
+                lru_size: s.get_prefixed_u64("lru_size"),
+                purge_size: s.get_prefixed_u64("purge_size"),
+                lru_limit: s.get_prefixed_u64("lru_limit"),
+            })
+            .collect()
+    }
+
+    pub fn call_pool(&self) -> Option<CallPool> {
+        let pool = self.section("global.callpool")?;
+        let mut stacks: Vec<CallStack> = Vec::new();
+        for section in self.sections_with_prefix("global.callpool.stack") {
+            if section.name.contains(".frame.") {
+                // Frames follow the stack they belong to
+                if let Some(stack) = stacks.last_mut() {
+                    stack.frames.push(section.clone());
+                }
+            } else {
+                stacks.push(CallStack {
+                    stack: section.clone(),
+                    frames: Vec::new(),
+                });
+            }
+        }
+        Some(CallPool {
+            count: pool.get_u64("callpool.cnt"),
+            stacks,
+        })
+    }
+}
+
+fn split_entry(line: &str) -> (String, String) {
+    match line.split_once('=') {
+        Some((key, value)) => (key.trim().to_string(), value.trim().to_string()),
+        None => (line.to_string(), String::new()),
+    }
+}
+
+impl FromStr for Statedump {
+    type Err = GlusterError;
+
+    fn from_str(dump: &str) -> Result<Statedump, GlusterError> {
+        let mut statedump = Statedump::default();
+        let mut current: Option<Section> = None;
+        for line in dump.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(time) = line.strip_prefix("DUMP-START-TIME:") {
+                statedump.start_time = Some(time.trim().to_string());
+            } else if let Some(time) = line.strip_prefix("DUMP-END-TIME:") {
+                statedump.end_time = Some(time.trim().to_string());
+            } else if line.starts_with('[') && line.ends_with(']') {
+                if let Some(section) = current.take() {
+                    statedump.sections.push(section);
+                }
+                current = Some(Section {
+                    name: line[1..line.len() - 1].to_string(),
+                    ..Default::default()
+                });
+            } else if let Some(ref mut section) = current {
+                if line == RECORD_SEPARATOR {
+                    section.records.push(Vec::new());
+                } else if let Some(record) = section.records.last_mut() {
+                    record.push(split_entry(line));
+                } else {
+                    section.entries.push(split_entry(line));
+                }
+            } else {
+                return Err(GlusterError::new(format!(
+                    "statedump line outside of a section: {}",
+                    line
+                )));
+            }
+        }
+        if let Some(section) = current.take() {
+            statedump.sections.push(section);
+        }
+        if statedump.sections.is_empty() {
+            return Err(GlusterError::new("statedump has no sections".into()));
+        }
+        Ok(statedump)
+    }
+}
+
+impl Gluster {
+    /// Write a statedump of this client into dir and return its path.
+    /// This sets the statedump path to dir and it stays set, so later
+    /// statedumps, including ones an admin asks for with SIGUSR1, are
+    /// written to dir as well.  Call set_statedump_path afterwards to send
+    /// them elsewhere.
+    pub fn trigger_statedump(&self, dir: &Path) -> Result<PathBuf, GlusterError> {
+        self.set_statedump_path(dir)?;
+        self.sysrq(SysRqKey::Statedump)?;
+        // The dump is named glusterdump.<pid>.dump.<timestamp>
+        let prefix = format!("glusterdump.{}.dump.", std::process::id());
+        let mut newest: Option<(u64, PathBuf)> = None;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            let timestamp = match file_name.strip_prefix(&prefix) {
+                Some(t) => t.parse::<u64>().unwrap_or(0),
+                None => continue,
+            };
+            match newest {
+                Some((t, _)) if t > timestamp => {}
+                _ => newest = Some((timestamp, entry.path())),
+            }
+        }
+        newest.map(|(_, path)| path).ok_or_else(|| {
+            GlusterError::new(format!("No statedump was written to {}", dir.display()))
+        })
+    }
+
+    /// Write a statedump of this client into dir and parse it.  Like
+    /// trigger_statedump this leaves the statedump path set to dir.
+    pub fn statedump(&self, dir: &Path) -> Result<Statedump, GlusterError> {
+        Statedump::from_file(&self.trigger_statedump(dir)?)
+    }
+}
@@ -0,0 +1,316 @@
+//! A Gluster connection that reconnects itself.
+//! The client graph can die underneath a long running process, for example
+//! while the trusted pool goes through a rolling upgrade.  Operations then
+//! keep failing with ENOTCONN.  SupervisedGluster notices this, tears the
+//! old connection down and runs the connect sequence again.  Operations
+//! that notice make a single attempt so they don't wait long.  Retrying
+//! with backoff is left to the monitor thread.
+use crate::gluster::{Gluster, GlusterBuilder, GlusterError};
+use libc::{
+    ECONNREFUSED, ECONNRESET, EHOSTUNREACH, EIO, ENETUNREACH, ENOTCONN, ESHUTDOWN, ETIMEDOUT,
+};
+
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, TryLockError, Weak};
+use std::thread;
+use std::time::Duration;
+
+/// Health of a supervised connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HealthState {
+    Healthy,
+    /// An operation or probe failed and the graph is considered dead
+    Unhealthy,
+    /// Reconnect attempt number n is starting
+    Reconnecting(u32),
+    /// Every reconnect attempt failed
+    Failed,
+}
+
+/// How reconnects are retried.  The delay starts at initial_delay and
+/// doubles after every failed attempt up to max_delay.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// None retries forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_attempts: Some(10),
+        }
+    }
+}
+
+/// Errors that mean the bricks or glusterd can't be reached, as opposed to
+/// errors about the request itself such as EACCES or ENOENT
+fn is_transport_error(e: &GlusterError) -> bool {
+    [
+        ENOTCONN,
+        EIO,
+        ECONNREFUSED,
+        ECONNRESET,
+        ETIMEDOUT,
+        EHOSTUNREACH,
+        ENETUNREACH,
+        ESHUTDOWN,
+    ]
+    .iter()
+    .any(|code| e.is_errno(*code))
+}
+
+type HealthCallback = Box<dyn Fn(HealthState) + Send + Sync>;
+
+struct Connection {
+    gluster: Arc<Gluster>,
+    /// Bumped on every reconnect so that callers that saw a failure on an
+    /// old connection don't reconnect again
+    generation: usize,
+}
+
+/// A Gluster handle that is reconnected when its client graph dies.
+/// Consecutive ENOTCONN errors from operations run through it or a probe
+/// that can't reach the volume trigger a reconnect.
+pub struct SupervisedGluster {
+    builder: GlusterBuilder,
+    backoff: Backoff,
+    /// Consecutive ENOTCONN errors before reconnecting
+    failure_threshold: usize,
+    consecutive_failures: AtomicUsize,
+    connection: RwLock<Connection>,
+    reconnect_lock: Mutex<()>,
+    on_health_change: Option<HealthCallback>,
+}
+
+impl SupervisedGluster {
+    /// Connect using builder.  The builder is kept to reconnect later.
+    pub fn new(builder: GlusterBuilder) -> Result<SupervisedGluster, GlusterError> {
+        let gluster = builder.connect()?;
+        Ok(SupervisedGluster {
+            builder,
+            backoff: Backoff::default(),
+            failure_threshold: 3,
+            consecutive_failures: AtomicUsize::new(0),
+            connection: RwLock::new(Connection {
+                gluster: Arc::new(gluster),
+                generation: 0,
+            }),
+            reconnect_lock: Mutex::new(()),
+            on_health_change: None,
+        })
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> SupervisedGluster {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Number of consecutive ENOTCONN errors that mark the graph as dead.
+    /// The default is 3.
+    pub fn failure_threshold(mut self, threshold: usize) -> SupervisedGluster {
+        self.failure_threshold = threshold.max(1);
+        self
+    }
+
+    /// Called whenever the health of the connection changes
+    pub fn on_health_change<F>(mut self, callback: F) -> SupervisedGluster
+    where
+        F: Fn(HealthState) + Send + Sync + 'static,
+    {
+        self.on_health_change = Some(Box::new(callback));
+        self
+    }
+
+    fn report(&self, state: HealthState) {
+        if let Some(ref callback) = self.on_health_change {
+            callback(state);
+        }
+    }
+
+    fn current(&self) -> (Arc<Gluster>, usize) {
+        let connection = self.connection.read().unwrap_or_else(|e| e.into_inner());
+        (Arc::clone(&connection.gluster), connection.generation)
+    }
+
+    /// The current connection.  It may be replaced by a reconnect at any
+    /// time so don't hold on to it for long.
+    pub fn get(&self) -> Arc<Gluster> {
+        self.current().0
+    }
+
+    /// Run an operation against the current connection.  If it fails with
+    /// ENOTCONN often enough in a row one attempt is made to re-establish
+    /// the connection.  Nothing waits for a reconnect another thread is
+    /// already running.  The error is returned either way because the
+    /// operation may or may not have reached the bricks before the graph
+    /// died.  Use run_idempotent to have it tried again.
+    pub fn run<T, F>(&self, op: F) -> Result<T, GlusterError>
+    where
+        F: Fn(&Gluster) -> Result<T, GlusterError>,
+    {
+        self.run_inner(op, false)
+    }
+
+    /// Like run but after a reconnect the operation is tried once more on
+    /// the new connection.  Only use this for operations that are safe to
+    /// run twice, such as reads, stat or writes at a fixed offset.  A
+    /// create with O_EXCL, a rename or an unlink that already happened
+    /// fails or does something else the second time.
+    pub fn run_idempotent<T, F>(&self, op: F) -> Result<T, GlusterError>
+    where
+        F: Fn(&Gluster) -> Result<T, GlusterError>,
+    {
+        self.run_inner(op, true)
+    }
+
+    fn run_inner<T, F>(&self, op: F, retry: bool) -> Result<T, GlusterError>
+    where
+        F: Fn(&Gluster) -> Result<T, GlusterError>,
+    {
+        let (gluster, generation) = self.current();
+        match op(&gluster) {
+            Ok(value) => {
+                self.consecutive_failures.store(0, Ordering::SeqCst);
+                Ok(value)
+            }
+            Err(e) => {
+                if !e.is_errno(ENOTCONN) {
+                    return Err(e);
+                }
+                let failures = self.consecutive_failures.fetch_add(1, Ordering::SeqCst) + 1;
+                if failures < self.failure_threshold {
+                    return Err(e);
+                }
+                drop(gluster);
+                if let Err(reconnect) = self.reconnect_from(generation, false) {
+                    debug!("reconnect after {:?} failed: {:?}", e, reconnect);
+                    return Err(e);
+                }
+                if !retry {
+                    return Err(e);
+                }
+                op(&self.get())
+            }
+        }
+    }
+
+    /// Check that the graph is alive with a statvfs of "/".  If that fails
+    /// because the volume can't be reached it reconnects, retrying with
+    /// backoff, so this can block for as long as the backoff allows.
+    /// Other errors such as EACCES are returned as they are.
+    pub fn probe(&self) -> Result<(), GlusterError> {
+        let (gluster, generation) = self.current();
+        let result = gluster.statvfs(Path::new("/"));
+        drop(gluster);
+        match result {
+            Ok(_) => {
+                self.consecutive_failures.store(0, Ordering::SeqCst);
+                Ok(())
+            }
+            Err(e) => {
+                debug!("probe failed: {:?}", e);
+                if !is_transport_error(&e) {
+                    return Err(e);
+                }
+                self.reconnect_from(generation, true)
+            }
+        }
+    }
+
+    /// Tear down the connection and connect again, retrying with backoff
+    pub fn reconnect(&self) -> Result<(), GlusterError> {
+        let (_, generation) = self.current();
+        self.reconnect_from(generation, true)
+    }
+
+    /// Replace the connection of generation.  With backoff attempts are
+    /// retried as configured, otherwise only one is made and nothing waits
+    /// for a reconnect that is already running.
+    fn reconnect_from(&self, generation: usize, backoff: bool) -> Result<(), GlusterError> {
+        let _guard = if backoff {
+            self.reconnect_lock
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+        } else {
+            match self.reconnect_lock.try_lock() {
+                Ok(guard) => guard,
+                Err(TryLockError::Poisoned(e)) => e.into_inner(),
+                Err(TryLockError::WouldBlock) => {
+                    return Err(GlusterError::new(
+                        "Another thread is reconnecting".to_string(),
+                    ));
+                }
+            }
+        };
+        if self.current().1 != generation {
+            // Somebody else already reconnected
+            return Ok(());
+        }
+        self.report(HealthState::Unhealthy);
+        let mut delay = self.backoff.initial_delay;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.report(HealthState::Reconnecting(attempt));
+            match self.builder.connect() {
+                Ok(gluster) => {
+                    // Replacing the dead connection drops it.  glfs_fini runs
+                    // once the last Arc handed out by get() is dropped too.
+                    let old = {
+                        let mut connection =
+                            self.connection.write().unwrap_or_else(|e| e.into_inner());
+                        connection.generation += 1;
+                        std::mem::replace(&mut connection.gluster, Arc::new(gluster))
+                    };
+                    drop(old);
+                    self.consecutive_failures.store(0, Ordering::SeqCst);
+                    self.report(HealthState::Healthy);
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!("reconnect attempt {} failed: {:?}", attempt, e);
+                    if !backoff {
+                        self.report(HealthState::Unhealthy);
+                        return Err(e);
+                    }
+                    if let Some(max) = self.backoff.max_attempts {
+                        if attempt >= max {
+                            self.report(HealthState::Failed);
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+            thread::sleep(delay);
+            delay = (delay * 2).min(self.backoff.max_delay);
+        }
+    }
+
+    /// Probe the connection every interval from a background thread.  The
+    /// thread exits once the SupervisedGluster is dropped.
+    pub fn spawn_monitor(
+        supervised: &Arc<SupervisedGluster>,
+        interval: Duration,
+    ) -> Result<thread::JoinHandle<()>, GlusterError> {
+        let weak: Weak<SupervisedGluster> = Arc::downgrade(supervised);
+        let handle = thread::Builder::new()
+            .name("gfapi-monitor".to_string())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                let supervised = match weak.upgrade() {
+                    Some(s) => s,
+                    None => return,
+                };
+                if let Err(e) = supervised.probe() {
+                    error!("gluster connection is down: {:?}", e);
+                }
+            })?;
+        Ok(handle)
+    }
+}
@@ -0,0 +1,287 @@
+//! Tokio support for GlusterFile, enabled with the tokio feature.
+//! AsyncGlusterFile reads and writes through gfapi's async calls so tokio's
+//! worker threads never wait on the network.  Seeking from the end needs
+//! the size of the file, which gfapi can only look up synchronously, so
+//! that runs on tokio's blocking pool instead.
+//! Like tokio::fs::File a write is reported as done as soon as it has been
+//! handed to gluster.  If it fails the error is returned by the next
+//! operation on the file.  Call flush to find out whether all writes made
+//! it.
+use crate::aio::{self, GlusterFuture};
+use crate::gluster::{GlusterError, GlusterFile};
+use crate::io::to_offset;
+use libc::SEEK_CUR;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+use tokio::task::JoinHandle;
+
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::mem;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+enum State {
+    Idle,
+    Reading(GlusterFuture<'static, Vec<u8>>),
+    /// A write and the offset it was started at
+    Writing(GlusterFuture<'static, (usize, Vec<u8>)>, u64),
+    /// Looking up the file size for a seek from the end
+    Seeking(JoinHandle<io::Result<u64>>, i64),
+}
+
+/// A GlusterFile for use from tokio.  It keeps its own file position and
+/// reads and writes at that position.
+/// The file isn't closed until any operation in flight has finished.  If
+/// one is when this is dropped, waiting for it and closing the file are
+/// left to a task on the runtime.
+pub struct AsyncGlusterFile {
+    state: State,
+    file: Arc<GlusterFile>,
+    pos: u64,
+    /// A seek started with start_seek.  Seeks from the current position
+    /// are turned into seeks from the start.
+    seek: Option<SeekFrom>,
+}
+
+impl AsyncGlusterFile {
+    /// Wrap file.  Reading and writing start at its current offset.
+    pub fn new(file: GlusterFile) -> Result<AsyncGlusterFile, GlusterError> {
+        let pos = file.lseek(0, SEEK_CUR)?;
+        Ok(AsyncGlusterFile {
+            state: State::Idle,
+            file: Arc::new(file),
+            pos: pos as u64,
+            seek: None,
+        })
+    }
+
+    /// The file being read and written.  Operations may still be in
+    /// flight on it.
+    pub fn get_ref(&self) -> &GlusterFile {
+        &self.file
+    }
+
+    /// The position the next read or write happens at
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Wait for operations in flight and return the file.  Its offset is
+    /// set to the position of this AsyncGlusterFile.
+    pub async fn into_inner(mut self) -> io::Result<GlusterFile> {
+        std::future::poll_fn(|cx| self.poll_pending(cx)).await?;
+        let pos = to_offset(self.pos)?;
+        self.file.lseek(pos, libc::SEEK_SET)?;
+        // Nothing is in flight any more so dropping self leaves file alone
+        let file = Arc::clone(&self.file);
+        drop(self);
+        Arc::try_unwrap(file).map_err(|_| io::Error::other("file is still in use"))
+    }
+
+    /// Drive whatever operation is in flight to completion
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            match self.state {
+                State::Idle => return Poll::Ready(Ok(())),
+                State::Reading(ref mut read) => {
+                    let result = match Pin::new(read).poll(cx) {
+                        Poll::Ready(result) => result,
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    // Nobody is waiting for the data any more.  The
+                    // position didn't move so it can be read again.
+                    self.state = State::Idle;
+                    result?;
+                }
+                State::Writing(ref mut write, offset) => {
+                    let result = match Pin::new(write).poll(cx) {
+                        Poll::Ready(result) => result,
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    self.state = State::Idle;
+                    let (written, mut buffer) = result?;
+                    if written == buffer.len() {
+                        continue;
+                    }
+                    if written == 0 {
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::WriteZero,
+                            "failed to write whole buffer",
+                        )));
+                    }
+                    // Continue a short write with the rest of the buffer
+                    let rest = buffer.split_off(written);
+                    let offset = offset + written as u64;
+                    let write = aio::pwrite_owned(&self.file, rest, to_offset(offset)?);
+                    self.state = State::Writing(write, offset);
+                }
+                State::Seeking(ref mut size, delta) => {
+                    let result = match Pin::new(size).poll(cx) {
+                        Poll::Ready(result) => result,
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    self.state = State::Idle;
+                    let size = result.map_err(io::Error::other)??;
+                    self.pos = seek_relative(size, delta)?;
+                }
+            }
+        }
+    }
+}
+
+impl State {
+    /// Wait for the operation in flight to finish
+    async fn settle(self) {
+        let result = match self {
+            State::Idle => Ok(()),
+            State::Reading(read) => read.await.map(drop),
+            State::Writing(write, _) => write.await.map(drop),
+            // The blocking task holds its own reference to the file
+            State::Seeking(..) => Ok(()),
+        };
+        if let Err(e) = result {
+            error!("Operation failed after the file was dropped: {:?}", e);
+        }
+    }
+}
+
+impl Drop for AsyncGlusterFile {
+    fn drop(&mut self) {
+        let state = mem::replace(&mut self.state, State::Idle);
+        if let State::Idle | State::Seeking(..) = state {
+            return;
+        }
+        // Closing the file blocks until the operation is done.  Without a
+        // runtime to hand that to the drop below has to block.
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let file = Arc::clone(&self.file);
+            runtime.spawn(async move {
+                state.settle().await;
+                drop(file);
+            });
+        }
+    }
+}
+
+fn seek_relative(base: u64, delta: i64) -> io::Result<u64> {
+    let pos = if delta < 0 {
+        base.checked_sub(delta.unsigned_abs())
+    } else {
+        base.checked_add(delta as u64)
+    };
+    pos.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )
+    })
+}
+
+impl AsyncRead for AsyncGlusterFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            match this.state {
+                State::Idle => {
+                    if buf.remaining() == 0 {
+                        return Poll::Ready(Ok(()));
+                    }
+                    let read = aio::pread(&this.file, buf.remaining(), to_offset(this.pos)?);
+                    this.state = State::Reading(read);
+                }
+                State::Reading(ref mut read) => {
+                    let result = match Pin::new(read).poll(cx) {
+                        Poll::Ready(result) => result,
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    this.state = State::Idle;
+                    let data = result?;
+                    // The caller may have passed a smaller buffer this time
+                    let len = data.len().min(buf.remaining());
+                    buf.put_slice(&data[..len]);
+                    this.pos += len as u64;
+                    return Poll::Ready(Ok(()));
+                }
+                _ => match this.poll_pending(cx) {
+                    Poll::Ready(Ok(())) => {}
+                    other => return other,
+                },
+            }
+        }
+    }
+}
+
+impl AsyncWrite for AsyncGlusterFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // Only one operation is in flight at a time
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+        let offset = this.pos;
+        let write = aio::pwrite_owned(&this.file, buf.to_vec(), to_offset(offset)?);
+        this.state = State::Writing(write, offset);
+        this.pos += buf.len() as u64;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+}
+
+impl AsyncSeek for AsyncGlusterFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        if this.seek.is_some() {
+            return Err(io::Error::other(
+                "other seek is pending, call poll_complete before start_seek",
+            ));
+        }
+        // The position already counts writes that are in flight so only
+        // seeks from the end have to wait for them
+        this.seek = Some(match position {
+            SeekFrom::Current(delta) => SeekFrom::Start(seek_relative(this.pos, delta)?),
+            position => position,
+        });
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        loop {
+            match this.poll_pending(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+            match this.seek.take() {
+                Some(SeekFrom::End(delta)) => {
+                    let file = Arc::clone(&this.file);
+                    let size = tokio::task::spawn_blocking(move || {
+                        let stat = file.fstat()?;
+                        Ok::<u64, io::Error>(stat.st_size as u64)
+                    });
+                    this.state = State::Seeking(size, delta);
+                }
+                Some(SeekFrom::Start(pos)) => this.pos = pos,
+                _ => return Poll::Ready(Ok(this.pos)),
+            }
+        }
+    }
+}
@@ -0,0 +1,349 @@
+//! Copy files between a volume and the local filesystem.
+//! Only the data of sparse files is transferred.  The destination is cut
+//! to the size of the source first which leaves it all hole, so there is
+//! nothing to punch, then the data extents are written into it.  Blocks
+//! of zeros inside data extents are skipped as well, a filesystem block at
+//! a time, so holes survive even where the source can't report them.
+//! Mode, owner, times and xattrs are carried over when asked for.
+use crate::copy::times_of;
+use crate::glfs::*;
+use crate::gluster::{get_error, sized_fetch, Gluster, GlusterError, GlusterFile};
+use crate::io::GlusterFileExt;
+use crate::sparse::ExtentKind;
+use libc::{c_char, c_void, timespec, EINVAL, ENXIO, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY};
+
+use std::ffi::{CStr, CString};
+use std::fs::{self, File};
+use std::io::Error;
+use std::os::unix::fs::{FileExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// How much is read and written at a time
+const TRANSFER_CHUNK: usize = 1024 * 1024;
+
+/// What to carry over besides the data.  Nothing is by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransferOptions {
+    /// Permission bits
+    pub mode: bool,
+    /// Owner and group.  Usually needs root on the destination.
+    pub owner: bool,
+    /// Access and modification times
+    pub times: bool,
+    /// Extended attributes.  Those in the trusted namespace and gluster's
+    /// own virtual ones are left out because they describe the source
+    /// filesystem.
+    pub xattrs: bool,
+}
+
+impl TransferOptions {
+    pub fn new() -> TransferOptions {
+        TransferOptions::default()
+    }
+
+    /// Carry over everything
+    pub fn all() -> TransferOptions {
+        TransferOptions {
+            mode: true,
+            owner: true,
+            times: true,
+            xattrs: true,
+        }
+    }
+
+    pub fn mode(mut self, mode: bool) -> TransferOptions {
+        self.mode = mode;
+        self
+    }
+
+    pub fn owner(mut self, owner: bool) -> TransferOptions {
+        self.owner = owner;
+        self
+    }
+
+    pub fn times(mut self, times: bool) -> TransferOptions {
+        self.times = times;
+        self
+    }
+
+    pub fn xattrs(mut self, xattrs: bool) -> TransferOptions {
+        self.xattrs = xattrs;
+        self
+    }
+}
+
+/// The granularity zeros are looked for at.  Filesystems can't keep a
+/// hole smaller than a block.
+const ZERO_BLOCK: usize = 4096;
+
+fn is_zero(buf: &[u8]) -> bool {
+    buf.iter().all(|b| *b == 0)
+}
+
+/// Call write for every run of blocks in buf that aren't all zero.  The
+/// destination is a hole where the blocks of zeros in between go.
+fn write_data<F>(buf: &[u8], offset: u64, mut write: F) -> Result<(), GlusterError>
+where
+    F: FnMut(&[u8], u64) -> Result<(), GlusterError>,
+{
+    let mut run = None;
+    for (i, block) in buf.chunks(ZERO_BLOCK).enumerate() {
+        let pos = i * ZERO_BLOCK;
+        match (is_zero(block), run) {
+            (true, Some(start)) => {
+                write(&buf[start..pos], offset + start as u64)?;
+                run = None;
+            }
+            (false, None) => run = Some(pos),
+            _ => {}
+        }
+    }
+    if let Some(start) = run {
+        write(&buf[start..], offset + start as u64)?;
+    }
+    Ok(())
+}
+
+fn should_copy_xattr(name: &CStr) -> bool {
+    let name = name.to_bytes();
+    !(name.starts_with(b"trusted.") || name.starts_with(b"glusterfs."))
+}
+
+/// Split a NUL separated list of xattr names
+fn split_names(list: &[u8]) -> Vec<CString> {
+    list.split(|b| *b == 0)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| CString::new(name).ok())
+        .collect()
+}
+
+fn local_error() -> GlusterError {
+    Error::last_os_error().into()
+}
+
+fn gluster_xattrs(file: &GlusterFile) -> Result<Vec<(CString, Vec<u8>)>, GlusterError> {
+    let list = sized_fetch(
+        |buf, size| unsafe { glfs_flistxattr(file.file_handle, buf, size) },
+        get_error,
+    )?;
+    let mut xattrs = Vec::new();
+    for name in split_names(&list)
+        .into_iter()
+        .filter(|n| should_copy_xattr(n))
+    {
+        let value = sized_fetch(
+            |buf, size| unsafe { glfs_fgetxattr(file.file_handle, name.as_ptr(), buf, size) },
+            get_error,
+        )?;
+        xattrs.push((name, value));
+    }
+    Ok(xattrs)
+}
+
+fn local_xattrs(file: &File) -> Result<Vec<(CString, Vec<u8>)>, GlusterError> {
+    let fd = file.as_raw_fd();
+    let list = sized_fetch(
+        |buf, size| unsafe { libc::flistxattr(fd, buf as *mut c_char, size) },
+        local_error,
+    )?;
+    let mut xattrs = Vec::new();
+    for name in split_names(&list)
+        .into_iter()
+        .filter(|n| should_copy_xattr(n))
+    {
+        let value = sized_fetch(
+            |buf, size| unsafe { libc::fgetxattr(fd, name.as_ptr(), buf, size) },
+            local_error,
+        )?;
+        xattrs.push((name, value));
+    }
+    Ok(xattrs)
+}
+
+/// Data ranges of a local file from SEEK_DATA and SEEK_HOLE.  Filesystems
+/// that don't support them get a single range covering the file.
+fn local_data_ranges(file: &File, size: u64) -> Result<Vec<(u64, u64)>, GlusterError> {
+    let fd = file.as_raw_fd();
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    while offset < size {
+        let data = unsafe { libc::lseek(fd, offset as i64, libc::SEEK_DATA) };
+        if data < 0 {
+            let e = Error::last_os_error();
+            match e.raw_os_error() {
+                // Nothing but hole up to the end
+                Some(ENXIO) => break,
+                Some(EINVAL) if offset == 0 => return Ok(vec![(0, size)]),
+                _ => return Err(e.into()),
+            }
+        }
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return Err(local_error());
+        }
+        let (start, end) = (data as u64, (hole as u64).min(size));
+        if end <= start {
+            break;
+        }
+        ranges.push((start, end));
+        offset = end;
+    }
+    Ok(ranges)
+}
+
+/// New files are private until the mode of the source is applied.
+/// Otherwise they get the usual mode, less the umask.
+fn create_mode(options: &TransferOptions) -> libc::mode_t {
+    if options.mode {
+        0o600
+    } else {
+        0o666
+    }
+}
+
+fn local_times(metadata: &fs::Metadata) -> [timespec; 2] {
+    [
+        timespec {
+            tv_sec: metadata.atime(),
+            tv_nsec: metadata.atime_nsec(),
+        },
+        timespec {
+            tv_sec: metadata.mtime(),
+            tv_nsec: metadata.mtime_nsec(),
+        },
+    ]
+}
+
+impl Gluster {
+    /// Copy the file at remote on the volume to local_path, replacing it
+    /// if it exists.  Holes in the remote file stay holes locally.
+    /// Returns the size of the file.
+    pub fn download(
+        &self,
+        remote: &Path,
+        local_path: &Path,
+        options: &TransferOptions,
+    ) -> Result<u64, GlusterError> {
+        let src = self.open(remote, O_RDONLY)?;
+        let stat = src.fstat()?;
+        let size = stat.st_size as u64;
+        let dst = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(create_mode(options))
+            .open(local_path)?;
+        dst.set_len(size)?;
+
+        let mut buffer = vec![0; TRANSFER_CHUNK];
+        for extent in src.extents()? {
+            let extent = extent?;
+            if extent.kind == ExtentKind::Hole {
+                continue;
+            }
+            let mut offset = extent.offset;
+            while offset < extent.end() {
+                let want = (extent.end() - offset).min(TRANSFER_CHUNK as u64) as usize;
+                let read = src.read_at(&mut buffer[..want], offset)?;
+                if read == 0 {
+                    // The file shrank underneath us
+                    break;
+                }
+                write_data(&buffer[..read], offset, |data, at| {
+                    Ok(dst.write_all_at(data, at)?)
+                })?;
+                offset += read as u64;
+            }
+        }
+
+        let fd = dst.as_raw_fd();
+        if options.xattrs {
+            for (name, value) in gluster_xattrs(&src)? {
+                let ret_code = unsafe {
+                    libc::fsetxattr(
+                        fd,
+                        name.as_ptr(),
+                        value.as_ptr() as *const c_void,
+                        value.len(),
+                        0,
+                    )
+                };
+                if ret_code < 0 {
+                    return Err(local_error());
+                }
+            }
+        }
+        // chown can clear the setuid and setgid bits so it goes before chmod
+        if options.owner && unsafe { libc::fchown(fd, stat.st_uid, stat.st_gid) } < 0 {
+            return Err(local_error());
+        }
+        if options.mode && unsafe { libc::fchmod(fd, stat.st_mode & 0o7777) } < 0 {
+            return Err(local_error());
+        }
+        // Times go last because everything above may touch them
+        if options.times && unsafe { libc::futimens(fd, times_of(&stat).as_ptr()) } < 0 {
+            return Err(local_error());
+        }
+        Ok(size)
+    }
+
+    /// Copy the local file at local_path to remote on the volume, replacing
+    /// it if it exists.  Holes in the local file stay holes on the volume.
+    /// Returns the size of the file.
+    pub fn upload(
+        &self,
+        local_path: &Path,
+        remote: &Path,
+        options: &TransferOptions,
+    ) -> Result<u64, GlusterError> {
+        let src = File::open(local_path)?;
+        let metadata = src.metadata()?;
+        let size = metadata.len();
+        let dst = self.create(remote, O_CREAT | O_WRONLY | O_TRUNC, create_mode(options))?;
+        dst.ftruncate(size as i64)?;
+
+        let mut buffer = vec![0; TRANSFER_CHUNK];
+        for (start, end) in local_data_ranges(&src, size)? {
+            let mut offset = start;
+            while offset < end {
+                let want = (end - offset).min(TRANSFER_CHUNK as u64) as usize;
+                let read = src.read_at(&mut buffer[..want], offset)?;
+                if read == 0 {
+                    break;
+                }
+                write_data(&buffer[..read], offset, |data, at| {
+                    Ok(dst.write_all_at(data, at)?)
+                })?;
+                offset += read as u64;
+            }
+        }
+
+        if options.xattrs {
+            for (name, value) in local_xattrs(&src)? {
+                let ret_code = unsafe {
+                    glfs_fsetxattr(
+                        dst.file_handle,
+                        name.as_ptr(),
+                        value.as_ptr() as *const c_void,
+                        value.len(),
+                        0,
+                    )
+                };
+                if ret_code < 0 {
+                    return Err(get_error());
+                }
+            }
+        }
+        if options.owner {
+            dst.fchown(metadata.uid(), metadata.gid())?;
+        }
+        if options.mode {
+            dst.fchmod(metadata.mode() & 0o7777)?;
+        }
+        if options.times {
+            dst.futimens(&local_times(&metadata))?;
+        }
+        Ok(size)
+    }
+}
@@ -0,0 +1,247 @@
+//! Upcall notifications from the bricks.  Gluster sends an upcall when
+//! another client changes an inode this client has cached or when a lease
+//! it holds has to be recalled.
+use crate::glfs::*;
+use crate::gluster::{get_error, Gluster, GlusterError};
+use crate::object::object_gfid;
+use libc::c_void;
+use uuid::Uuid;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const GLFS_EVENT_INODE_INVALIDATE: u32 = 0x0000_0001;
+const GLFS_EVENT_RECALL_LEASE: u32 = 0x0000_0002;
+
+/// The link count changed
+pub const GFAPI_UP_NLINK: u64 = 0x0000_0001;
+/// The mode changed
+pub const GFAPI_UP_MODE: u64 = 0x0000_0002;
+/// The owner changed
+pub const GFAPI_UP_OWN: u64 = 0x0000_0004;
+/// The size changed
+pub const GFAPI_UP_SIZE: u64 = 0x0000_0008;
+/// The mtime and ctime changed
+pub const GFAPI_UP_TIMES: u64 = 0x0000_0010;
+/// The atime changed
+pub const GFAPI_UP_ATIME: u64 = 0x0000_0020;
+/// The permissions changed
+pub const GFAPI_UP_PERM: u64 = 0x0000_0040;
+/// The inode was renamed
+pub const GFAPI_UP_RENAME: u64 = 0x0000_0080;
+/// The inode should be forgotten
+pub const GFAPI_UP_FORGET: u64 = 0x0000_0100;
+/// The times of the parent directory changed
+pub const GFAPI_UP_PARENT_TIMES: u64 = 0x0000_0200;
+
+/// The kind of lease being recalled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaseType {
+    Read,
+    ReadWrite,
+    Unknown(u32),
+}
+
+impl From<u32> for LeaseType {
+    fn from(lease_type: u32) -> LeaseType {
+        match lease_type {
+            1 => LeaseType::Read,
+            2 => LeaseType::ReadWrite,
+            other => LeaseType::Unknown(other),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UpcallEvent {
+    /// Cached attributes or data of an inode are no longer valid.
+    /// flags is a mask of the GFAPI_UP_* constants.
+    InodeInvalidate {
+        gfid: Uuid,
+        flags: u64,
+        expire_time_attr: u64,
+        parent_gfid: Option<Uuid>,
+        old_parent_gfid: Option<Uuid>,
+    },
+    /// Another client wants access that conflicts with a lease held on gfid
+    RecallLease { gfid: Uuid, lease_type: LeaseType },
+}
+
+impl UpcallEvent {
+    /// True if flag (one of the GFAPI_UP_* constants) is set on an
+    /// inode invalidation
+    pub fn has_flag(&self, flag: u64) -> bool {
+        match *self {
+            UpcallEvent::InodeInvalidate { flags, .. } => flags & flag == flag,
+            UpcallEvent::RecallLease { .. } => false,
+        }
+    }
+}
+
+type EventSender = Sender<Result<UpcallEvent, GlusterError>>;
+
+/// The upcall registration of a Gluster handle.  It lives as long as the
+/// handle so a callback already running on one of gluster's threads when
+/// the stream is dropped still has somewhere to send to.
+#[derive(Debug, Default)]
+pub(crate) struct UpcallRegistration {
+    registered: AtomicBool,
+    /// None while no stream is reading
+    sender: Mutex<Option<EventSender>>,
+}
+
+/// A stream of upcall events for a Gluster handle.  Events are delivered
+/// by gluster's threads and queued until they are read.  Dropping the
+/// stream unregisters from upcalls.
+pub struct UpcallStream<'a> {
+    gluster: &'a Gluster,
+    events: Receiver<Result<UpcallEvent, GlusterError>>,
+}
+
+impl<'a> UpcallStream<'a> {
+    /// Return the next event if one is queued without blocking
+    pub fn try_next(&self) -> Option<Result<UpcallEvent, GlusterError>> {
+        match self.events.try_recv() {
+            Ok(event) => Some(event),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+        }
+    }
+
+    /// Wait up to timeout for the next event
+    pub fn next_timeout(&self, timeout: Duration) -> Option<Result<UpcallEvent, GlusterError>> {
+        match self.events.recv_timeout(timeout) {
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+}
+
+impl<'a> Iterator for UpcallStream<'a> {
+    type Item = Result<UpcallEvent, GlusterError>;
+    /// Blocks until the next event arrives
+    fn next(&mut self) -> Option<Self::Item> {
+        self.events.recv().ok()
+    }
+}
+
+impl<'a> Drop for UpcallStream<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            let ret_code = glfs_upcall_unregister(
+                self.gluster.cluster_handle,
+                GLFS_EVENT_INODE_INVALIDATE | GLFS_EVENT_RECALL_LEASE,
+            );
+            if ret_code < 0 {
+                error!("{:?}", get_error());
+            }
+        }
+        // Unregistering doesn't wait for callbacks that are running.
+        // They find the sender gone and drop their event.
+        let upcalls = &self.gluster.upcalls;
+        if let Ok(mut sender) = upcalls.sender.lock() {
+            *sender = None;
+        }
+        upcalls.registered.store(false, Ordering::SeqCst);
+    }
+}
+
+fn optional_gfid(object: *mut glfs_object) -> Result<Option<Uuid>, GlusterError> {
+    if object.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(object_gfid(object)?))
+}
+
+/// Convert an upcall into an event.  None is returned for event types this
+/// crate doesn't know about.
+unsafe fn parse_upcall(upcall: *mut glfs_upcall) -> Option<Result<UpcallEvent, GlusterError>> {
+    let reason = glfs_upcall_get_reason(upcall);
+    let event = glfs_upcall_get_event(upcall);
+    if event.is_null() {
+        return None;
+    }
+    if reason == glfs_upcall_reason_GLFS_UPCALL_INODE_INVALIDATE {
+        let inode = event as *mut glfs_upcall_inode;
+        let parse = || -> Result<UpcallEvent, GlusterError> {
+            Ok(UpcallEvent::InodeInvalidate {
+                gfid: object_gfid(glfs_upcall_inode_get_object(inode))?,
+                flags: glfs_upcall_inode_get_flags(inode),
+                expire_time_attr: glfs_upcall_inode_get_expire(inode),
+                parent_gfid: optional_gfid(glfs_upcall_inode_get_pobject(inode))?,
+                old_parent_gfid: optional_gfid(glfs_upcall_inode_get_oldpobject(inode))?,
+            })
+        };
+        Some(parse())
+    } else if reason == glfs_upcall_reason_GLFS_UPCALL_RECALL_LEASE {
+        let lease = event as *mut glfs_upcall_lease;
+        Some(
+            object_gfid(glfs_upcall_lease_get_object(lease)).map(|gfid| UpcallEvent::RecallLease {
+                gfid,
+                lease_type: LeaseType::from(glfs_upcall_lease_get_lease_type(lease)),
+            }),
+        )
+    } else {
+        None
+    }
+}
+
+/// Called by gluster for every upcall.  The upcall is owned by us and
+/// has to be released with glfs_free.
+unsafe extern "C" fn upcall_callback(upcall: *mut glfs_upcall, data: *mut c_void) {
+    if upcall.is_null() {
+        return;
+    }
+    let parsed = parse_upcall(upcall);
+    glfs_free(upcall as *mut c_void);
+    if let Some(event) = parsed {
+        let upcalls = &*(data as *const UpcallRegistration);
+        if let Ok(sender) = upcalls.sender.lock() {
+            if let Some(ref sender) = *sender {
+                let _ = sender.send(event);
+            }
+        }
+    }
+}
+
+impl Gluster {
+    /// Register for upcalls and return a stream of inode invalidations and
+    /// lease recalls.  Only one stream can be registered per Gluster handle
+    /// at a time.  Asking for another one is an error until the first is
+    /// dropped.
+    /// The volume needs features.cache-invalidation turned on for inode
+    /// invalidations to be sent.
+    pub fn upcall_stream(&self) -> Result<UpcallStream<'_>, GlusterError> {
+        let upcalls = &self.upcalls;
+        if upcalls.registered.swap(true, Ordering::SeqCst) {
+            return Err(GlusterError::new(
+                "An upcall stream is already registered for this handle".into(),
+            ));
+        }
+        let (tx, rx) = channel();
+        if let Ok(mut sender) = upcalls.sender.lock() {
+            *sender = Some(tx);
+        }
+        unsafe {
+            let ret_code = glfs_upcall_register(
+                self.cluster_handle,
+                GLFS_EVENT_INODE_INVALIDATE | GLFS_EVENT_RECALL_LEASE,
+                Some(upcall_callback),
+                &**upcalls as *const UpcallRegistration as *mut c_void,
+            );
+            if ret_code < 0 {
+                let error = get_error();
+                if let Ok(mut sender) = upcalls.sender.lock() {
+                    *sender = None;
+                }
+                upcalls.registered.store(false, Ordering::SeqCst);
+                return Err(error);
+            }
+        }
+        Ok(UpcallStream {
+            gluster: self,
+            events: rx,
+        })
+    }
+}
@@ -0,0 +1,389 @@
+//! Parse gluster volfiles into a graph of translators.
+//! A volfile is a list of volume definitions.  Each one names a translator,
+//! gives its type and options and lists the translators below it:
+//!
+//! ```text
+//! volume test-client-0
+//!     type protocol/client
+//!     option remote-host server1
+//!     option remote-subvolume /bricks/b1
+//! end-volume
+//! ```
+//!
+//! A translator has to be defined before it is used as a subvolume so the
+//! last definition is the top of the graph.
+//!
+//! Graphs can be edited and written back out with to_string() for use with
+//! GlusterBuilder::volfile.
+use crate::gluster::{Gluster, GlusterError, Volfile};
+
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// A single translator in the graph
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Xlator {
+    pub name: String,
+    /// For example protocol/client or cluster/replicate
+    pub xlator_type: String,
+    /// Options in the order they appear in the volfile
+    pub options: Vec<(String, String)>,
+    /// Names of the translators directly below this one
+    pub subvolumes: Vec<String>,
+}
+
+impl Xlator {
+    pub fn new(name: &str, xlator_type: &str) -> Xlator {
+        Xlator {
+            name: name.to_string(),
+            xlator_type: xlator_type.to_string(),
+            options: Vec::new(),
+            subvolumes: Vec::new(),
+        }
+    }
+
+    /// Set an option, replacing any existing value
+    pub fn set_option(&mut self, key: &str, value: &str) {
+        match self.options.iter_mut().find(|(k, _)| k == key) {
+            Some(option) => option.1 = value.to_string(),
+            None => self.options.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    /// Remove an option and return its value
+    pub fn remove_option(&mut self, key: &str) -> Option<String> {
+        let i = self.options.iter().position(|(k, _)| k == key)?;
+        Some(self.options.remove(i).1)
+    }
+
+    /// Look up the value of an option
+    pub fn option(&self, key: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// A brick as seen by the client graph
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Brick {
+    /// Name of the protocol/client translator
+    pub name: String,
+    pub host: String,
+    pub path: String,
+}
+
+/// The translator graph described by a volfile
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VolumeGraph {
+    /// Translators in definition order.  Subvolumes always come before the
+    /// translators that use them.
+    pub xlators: Vec<Xlator>,
+}
+
+impl VolumeGraph {
+    /// The translator at the top of the graph
+    pub fn top(&self) -> Option<&Xlator> {
+        self.xlators.last()
+    }
+
+    pub fn xlator(&self, name: &str) -> Option<&Xlator> {
+        self.xlators.iter().find(|x| x.name == name)
+    }
+
+    pub fn xlator_mut(&mut self, name: &str) -> Option<&mut Xlator> {
+        self.xlators.iter_mut().find(|x| x.name == name)
+    }
+
+    /// Remove a translator from the graph.  Translators that used it as a
+    /// subvolume are connected to its subvolumes instead.  This is how a
+    /// performance translator such as write-behind is dropped.
+    pub fn remove_xlator(&mut self, name: &str) -> Result<Xlator, GlusterError> {
+        let i = self
+            .xlators
+            .iter()
+            .position(|x| x.name == name)
+            .ok_or_else(|| GlusterError::new(format!("volume {} not found", name)))?;
+        let removed = self.xlators.remove(i);
+        for xlator in self.xlators.iter_mut() {
+            if let Some(pos) = xlator.subvolumes.iter().position(|s| s == name) {
+                xlator
+                    .subvolumes
+                    .splice(pos..=pos, removed.subvolumes.iter().cloned());
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Insert a translator directly above an existing one.  The new
+    /// translator gets below as its only subvolume and everything that used
+    /// below now uses the new translator.  This is how a debug translator
+    /// is added to a graph.
+    pub fn insert_above(&mut self, below: &str, mut xlator: Xlator) -> Result<(), GlusterError> {
+        if self.xlator(&xlator.name).is_some() {
+            return Err(GlusterError::new(format!(
+                "volume {} already exists",
+                xlator.name
+            )));
+        }
+        let i = self
+            .xlators
+            .iter()
+            .position(|x| x.name == below)
+            .ok_or_else(|| GlusterError::new(format!("volume {} not found", below)))?;
+        for parent in self.xlators.iter_mut() {
+            for subvolume in parent.subvolumes.iter_mut() {
+                if subvolume == below {
+                    *subvolume = xlator.name.clone();
+                }
+            }
+        }
+        xlator.subvolumes = vec![below.to_string()];
+        self.xlators.insert(i + 1, xlator);
+        Ok(())
+    }
+
+    /// All translators of the given type, for example "cluster/replicate"
+    pub fn xlators_of_type<'a>(&'a self, xlator_type: &'a str) -> impl Iterator<Item = &'a Xlator> {
+        self.xlators
+            .iter()
+            .filter(move |x| x.xlator_type == xlator_type)
+    }
+
+    /// Every brick the client connects to
+    pub fn bricks(&self) -> Vec<Brick> {
+        self.xlators_of_type("protocol/client")
+            .map(to_brick)
+            .collect()
+    }
+
+    /// The bricks underneath a translator
+    pub fn bricks_under(&self, name: &str) -> Vec<Brick> {
+        let mut bricks = Vec::new();
+        let mut seen = HashSet::new();
+        let mut stack = vec![name];
+        while let Some(name) = stack.pop() {
+            if !seen.insert(name) {
+                continue;
+            }
+            if let Some(xlator) = self.xlator(name) {
+                if xlator.xlator_type == "protocol/client" {
+                    bricks.push(to_brick(xlator));
+                }
+                // Push in reverse so bricks come out in volfile order
+                stack.extend(xlator.subvolumes.iter().rev().map(|s| s.as_str()));
+            }
+        }
+        bricks
+    }
+
+    /// The bricks of each replica set
+    pub fn replica_sets(&self) -> Vec<Vec<Brick>> {
+        self.xlators
+            .iter()
+            .filter(|x| x.xlator_type == "cluster/replicate" || x.xlator_type == "cluster/afr")
+            .map(|x| self.bricks_under(&x.name))
+            .collect()
+    }
+
+    /// The bricks of each disperse set
+    pub fn disperse_sets(&self) -> Vec<Vec<Brick>> {
+        self.xlators_of_type("cluster/disperse")
+            .map(|x| self.bricks_under(&x.name))
+            .collect()
+    }
+
+    /// The subvolumes that DHT distributes files across.  These are the
+    /// replica or disperse sets, or the bricks of a pure distribute volume.
+    pub fn dht_subvolumes(&self) -> Vec<&Xlator> {
+        self.xlators
+            .iter()
+            .filter(|x| x.xlator_type == "cluster/distribute" || x.xlator_type == "cluster/dht")
+            .flat_map(|x| x.subvolumes.iter())
+            .filter_map(|name| self.xlator(name))
+            .collect()
+    }
+}
+
+fn to_brick(xlator: &Xlator) -> Brick {
+    Brick {
+        name: xlator.name.clone(),
+        host: xlator.option("remote-host").unwrap_or("").to_string(),
+        path: xlator.option("remote-subvolume").unwrap_or("").to_string(),
+    }
+}
+
+fn write_value(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    if value.is_empty() || value.contains(char::is_whitespace) {
+        write!(f, "\"{}\"", value)
+    } else {
+        f.write_str(value)
+    }
+}
+
+impl fmt::Display for Xlator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "volume {}", self.name)?;
+        writeln!(f, "    type {}", self.xlator_type)?;
+        for (key, value) in &self.options {
+            write!(f, "    option {} ", key)?;
+            write_value(f, value)?;
+            writeln!(f)?;
+        }
+        if !self.subvolumes.is_empty() {
+            writeln!(f, "    subvolumes {}", self.subvolumes.join(" "))?;
+        }
+        writeln!(f, "end-volume")
+    }
+}
+
+/// Writes the graph out in volfile syntax
+impl fmt::Display for VolumeGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, xlator) in self.xlators.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", xlator)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> From<&'a VolumeGraph> for Volfile {
+    fn from(graph: &'a VolumeGraph) -> Volfile {
+        Volfile::Text(graph.to_string())
+    }
+}
+
+fn parse_error(line: usize, msg: &str) -> GlusterError {
+    GlusterError::new(format!("volfile line {}: {}", line, msg))
+}
+
+/// Split a line into its keyword and the rest of the line
+fn split_keyword(line: &str) -> (&str, &str) {
+    match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, ""),
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    }
+}
+
+impl FromStr for VolumeGraph {
+    type Err = GlusterError;
+
+    fn from_str(volfile: &str) -> Result<VolumeGraph, GlusterError> {
+        let mut xlators: Vec<Xlator> = Vec::new();
+        let mut defined: HashSet<String> = HashSet::new();
+        let mut current: Option<(usize, Xlator)> = None;
+
+        for (i, line) in volfile.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, rest) = split_keyword(line);
+            match (keyword, current.as_mut()) {
+                ("volume", None) => {
+                    if rest.is_empty() || rest.contains(char::is_whitespace) {
+                        return Err(parse_error(line_no, "volume needs a single name"));
+                    }
+                    if defined.contains(rest) {
+                        return Err(parse_error(
+                            line_no,
+                            &format!("volume {} is defined twice", rest),
+                        ));
+                    }
+                    current = Some((
+                        line_no,
+                        Xlator {
+                            name: rest.to_string(),
+                            xlator_type: String::new(),
+                            options: Vec::new(),
+                            subvolumes: Vec::new(),
+                        },
+                    ));
+                }
+                ("volume", Some(_)) => {
+                    return Err(parse_error(line_no, "volume started before end-volume"));
+                }
+                ("type", Some((_, xlator))) => {
+                    if rest.is_empty() {
+                        return Err(parse_error(line_no, "type needs a value"));
+                    }
+                    xlator.xlator_type = rest.to_string();
+                }
+                ("option", Some((_, xlator))) => {
+                    let (key, value) = split_keyword(rest);
+                    if key.is_empty() {
+                        return Err(parse_error(line_no, "option needs a key"));
+                    }
+                    xlator
+                        .options
+                        .push((key.to_string(), unquote(value).to_string()));
+                }
+                ("subvolumes", Some((_, xlator))) => {
+                    for subvolume in rest.split_whitespace() {
+                        if !defined.contains(subvolume) {
+                            return Err(parse_error(
+                                line_no,
+                                &format!("subvolume {} is not defined", subvolume),
+                            ));
+                        }
+                        xlator.subvolumes.push(subvolume.to_string());
+                    }
+                }
+                ("end-volume", Some(_)) => {
+                    let (start, xlator) = current.take().unwrap();
+                    if xlator.xlator_type.is_empty() {
+                        return Err(parse_error(
+                            start,
+                            &format!("volume {} has no type", xlator.name),
+                        ));
+                    }
+                    defined.insert(xlator.name.clone());
+                    xlators.push(xlator);
+                }
+                (_, None) => {
+                    return Err(parse_error(
+                        line_no,
+                        &format!("{} outside of a volume definition", keyword),
+                    ));
+                }
+                (_, Some(_)) => {
+                    return Err(parse_error(
+                        line_no,
+                        &format!("unknown keyword {}", keyword),
+                    ));
+                }
+            }
+        }
+        if let Some((start, xlator)) = current {
+            return Err(parse_error(
+                start,
+                &format!("volume {} is missing end-volume", xlator.name),
+            ));
+        }
+        if xlators.is_empty() {
+            return Err(GlusterError::new("volfile has no volumes".into()));
+        }
+        Ok(VolumeGraph { xlators })
+    }
+}
+
+impl Gluster {
+    /// Fetch the volfile and parse it into a volume graph
+    pub fn volume_graph(&self) -> Result<VolumeGraph, GlusterError> {
+        self.get_volfile()?.parse()
+    }
+}
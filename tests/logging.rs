@@ -0,0 +1,80 @@
+use gfapi_sys::gluster::{GlusterBuilder, GlusterLogLevel, VolfileServer};
+use gfapi_sys::logging::LogLine;
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Keeps the target and message of every gfapi record
+struct Capture {
+    records: Mutex<Vec<(String, String)>>,
+}
+
+impl log::Log for Capture {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.target().starts_with("gfapi")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            self.records
+                .lock()
+                .unwrap()
+                .push((record.target().to_string(), record.args().to_string()));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE: Capture = Capture {
+    records: Mutex::new(Vec::new()),
+};
+
+#[test]
+fn parse_log_lines() {
+    let line = LogLine::parse(
+        "[2020-06-02 10:15:42.123456 +0000] I [MSGID: 101190] \
+         [event-epoll.c:670:event_dispatch_epoll_worker] 0-epoll: Started thread with index 1 []",
+    )
+    .unwrap();
+    assert_eq!(line.timestamp, "2020-06-02 10:15:42.123456 +0000");
+    assert_eq!(line.level, GlusterLogLevel::Info);
+    assert_eq!(line.msgid, Some(101190));
+    assert_eq!(line.file, "event-epoll.c");
+    assert_eq!(line.line, 670);
+    assert_eq!(line.function, "event_dispatch_epoll_worker");
+    assert_eq!(line.xlator, "epoll");
+    assert_eq!(line.message, "Started thread with index 1 []");
+
+    let line = LogLine::parse(
+        "[2020-06-02 10:15:43.000001] E [client-handshake.c:1234:client_query_portmap_cbk] \
+         0-test-client-0: failed to get the port number for remote subvolume",
+    )
+    .unwrap();
+    assert_eq!(line.level, GlusterLogLevel::Error);
+    assert_eq!(line.msgid, None);
+    assert_eq!(line.xlator, "test-client-0");
+    assert_eq!(line.level.to_log_level(), Some(log::Level::Error));
+
+    assert!(LogLine::parse("continuation of the previous message").is_none());
+}
+
+#[test]
+// Messages written before glfs_init returns reach the log crate
+fn forward_logging_test() {
+    log::set_logger(&CAPTURE).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+    // Nothing listens on port 1 so glfs_init fails and logs why
+    let result = GlusterBuilder::new("test")
+        .server(VolfileServer::tcp("localhost", 1))
+        .forward_logging(GlusterLogLevel::Info)
+        .connect();
+    assert!(result.is_err());
+    // The gfapi-log thread forwards them in the background
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while CAPTURE.records.lock().unwrap().is_empty() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(!CAPTURE.records.lock().unwrap().is_empty());
+}
@@ -0,0 +1,69 @@
+use gfapi_sys::statedump::*;
+
+const DUMP: &str = include_str!("statedumps/glusterdump.dump");
+
+#[test]
+fn parse_statedump() {
+    let dump: Statedump = DUMP.parse().unwrap();
+    assert_eq!(
+        dump.start_time.as_deref(),
+        Some("2020-06-02 10:15:42.123456")
+    );
+    assert_eq!(dump.end_time.as_deref(), Some("2020-06-02 10:15:42.234567"));
+    assert_eq!(
+        dump.section("mallinfo").unwrap().get_u64("mallinfo_arena"),
+        2236416
+    );
+
+    let usage = dump.memory_usage();
+    assert_eq!(usage.len(), 2);
+    assert_eq!(usage[1].xlator, "test-md-cache");
+    assert_eq!(usage[1].type_name, "gf_mdc_mt_md_cache_t");
+    assert_eq!(usage[1].num_allocs, 8192);
+    assert_eq!(usage[1].total_allocs, 9000);
+
+    let pools = dump.memory_pools();
+    assert_eq!(pools.len(), 2);
+    // Current releases write active-count, sizeof-type and friends
+    assert_eq!(pools[0].name, "dict_t");
+    assert_eq!(pools[0].active_count, 12);
+    assert_eq!(pools[0].sizeof_type, 160);
+    assert_eq!(pools[0].padded_sizeof, 256);
+    assert_eq!(pools[0].size, 3072);
+    assert_eq!(pools[0].shared_pool.as_deref(), Some("0x7f0"));
+    // Older ones hot-count and padded_sizeof
+    assert_eq!(pools[1].name, "inode_t");
+    assert_eq!(pools[1].active_count, 4);
+    assert_eq!(pools[1].padded_sizeof, 172);
+    assert_eq!(pools[1].shared_pool, None);
+    assert_eq!(pools[1].cold_count, 124);
+    assert_eq!(pools[1].alloc_count, 300);
+
+    let client: Vec<&Section> = dump
+        .sections_with_prefix("xlator.protocol.client.test-client-0")
+        .collect();
+    assert_eq!(client.len(), 1);
+    assert_eq!(client[0].get("connected"), Some("1"));
+
+    let tables = dump.inode_tables();
+    assert_eq!(tables.len(), 1);
+    assert_eq!(tables[0].name, "xlator.mount.api.itable");
+    assert_eq!(tables[0].active_size, 2);
+    assert_eq!(tables[0].lru_size, 10);
+
+    let pool = dump.call_pool().unwrap();
+    assert_eq!(pool.count, 1);
+    assert_eq!(pool.stacks.len(), 1);
+    assert_eq!(pool.stacks[0].stack.get("op"), Some("LOOKUP"));
+    assert_eq!(pool.stacks[0].frames.len(), 2);
+    assert_eq!(
+        pool.stacks[0].frames[1].get("translator"),
+        Some("test-replicate-0")
+    );
+}
+
+#[test]
+fn parse_errors() {
+    assert!("".parse::<Statedump>().is_err());
+    assert!("key=value\n".parse::<Statedump>().is_err());
+}
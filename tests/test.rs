@@ -29,11 +29,12 @@ fn integration_test1() {
     println!("Wrote {} bytes to gfapi/test", bytes_written);
     println!("Seeking back to 0");
     file_handle.lseek(0, SEEK_SET).unwrap();
-    let mut read_buff: Vec<u8> = Vec::with_capacity(1024);
+    let mut read_buff = [0; 1024];
     println!("Read back test file");
-    let bytes_read = file_handle.read(&mut read_buff, 1024, 0).unwrap();
+    let bytes_read = file_handle.read(&mut read_buff).unwrap();
     println!("Read {} bytes from gfapi/test", bytes_read);
-    assert_eq!(bytes_written, bytes_read);
+    assert_eq!(bytes_written as usize, bytes_read);
+    assert_eq!(&read_buff[..bytes_read], b"hello world");
     let file_times = [
         timespec {
             tv_sec: 0,
@@ -52,3 +53,944 @@ fn integration_test1() {
         println!("Dir_entry: {:?}", dir_entry);
     }
 }
+
+#[test]
+// Create a file through object handles and reopen it from its GFID like a
+// restarted process would.
+fn object_handle_test() {
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let root = cluster.root_object().unwrap();
+    let dir = root.mkdir(&Path::new("gfapi_objects"), S_IRWXU).unwrap();
+    let file = dir
+        .creat(&Path::new("file"), O_CREAT | O_RDWR, S_IRWXU)
+        .unwrap();
+    let gfid = file.gfid().unwrap();
+    drop(file);
+
+    let reopened = cluster.object_from_gfid(&gfid).unwrap();
+    assert_eq!(reopened.gfid().unwrap(), gfid);
+    let file_handle = reopened.open(O_RDWR).unwrap();
+    file_handle.write(b"hello", 0).unwrap();
+
+    dir.unlink(&Path::new("file")).unwrap();
+    root.unlink(&Path::new("gfapi_objects")).unwrap();
+}
+
+#[test]
+fn gfid_test() {
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let path = Path::new("/gfapi_gfid");
+    let file_handle = cluster.create(&path, O_CREAT | O_RDWR, S_IRWXU).unwrap();
+    drop(file_handle);
+
+    let gfid = cluster.gfid(&path).unwrap();
+    let object = cluster.lookup_object(&path, false).unwrap();
+    assert_eq!(object.gfid().unwrap(), gfid);
+    drop(object);
+
+    let file_handle = cluster.open_by_gfid(&gfid, O_RDWR).unwrap();
+    file_handle.write(b"hello", 0).unwrap();
+    drop(file_handle);
+
+    cluster.unlink(&path).unwrap();
+}
+
+#[test]
+fn std_io_test() {
+    use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let path = Path::new("/gfapi_std_io");
+    let mut file_handle = cluster
+        .create(&path, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+        .unwrap();
+    Write::write_all(&mut file_handle, b"first line\nsecond line\n").unwrap();
+    assert_eq!(file_handle.seek(SeekFrom::Start(0)).unwrap(), 0);
+
+    let mut lines = BufReader::new(&file_handle).lines();
+    assert_eq!(lines.next().unwrap().unwrap(), "first line");
+    assert_eq!(lines.next().unwrap().unwrap(), "second line");
+    assert!(lines.next().is_none());
+
+    (&file_handle).seek(SeekFrom::Start(6)).unwrap();
+    let mut contents = String::new();
+    Read::read_to_string(&mut file_handle, &mut contents).unwrap();
+    assert_eq!(contents, "line\nsecond line\n");
+    drop(file_handle);
+
+    cluster.unlink(&path).unwrap();
+}
+
+#[test]
+fn read_test() {
+    use gfapi_sys::io::GlusterFileExt;
+    use std::mem::MaybeUninit;
+
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let path = Path::new("/gfapi_read");
+    let file_handle = cluster
+        .create(&path, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+        .unwrap();
+    file_handle.write(b"0123456789", 0).unwrap();
+    file_handle.lseek(2, SEEK_SET).unwrap();
+
+    // read honours the file offset and advances it
+    let mut buf = [0; 3];
+    assert_eq!(file_handle.read(&mut buf).unwrap(), 3);
+    assert_eq!(&buf, b"234");
+    assert_eq!(file_handle.read(&mut buf).unwrap(), 3);
+    assert_eq!(&buf, b"567");
+
+    // read_at doesn't move it
+    assert_eq!(file_handle.read_at(&mut buf, 0).unwrap(), 3);
+    assert_eq!(&buf, b"012");
+    let mut rest = Vec::new();
+    assert_eq!(file_handle.read_to_end(&mut rest).unwrap(), 2);
+    assert_eq!(rest, b"89");
+
+    let mut exact = [0; 4];
+    file_handle.read_exact_at(&mut exact, 6).unwrap();
+    assert_eq!(&exact, b"6789");
+    assert!(file_handle.read_exact_at(&mut exact, 8).is_err());
+
+    let mut uninit = [MaybeUninit::uninit(); 16];
+    let filled = file_handle.read_at_uninit(&mut uninit, 4).unwrap();
+    assert_eq!(filled, b"456789");
+    drop(file_handle);
+
+    cluster.unlink(&path).unwrap();
+}
+
+#[test]
+fn positional_io_test() {
+    use gfapi_sys::io::GlusterFileExt;
+    use std::io::{IoSlice, IoSliceMut};
+    use std::sync::Arc;
+    use std::thread;
+
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let path = Path::new("/gfapi_positional");
+    let file_handle = Arc::new(
+        cluster
+            .create(&path, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+            .unwrap(),
+    );
+
+    // Each thread writes its own chunk of the shared fd
+    let workers: Vec<_> = (0..4u8)
+        .map(|i| {
+            let file_handle = Arc::clone(&file_handle);
+            thread::spawn(move || {
+                let chunk = [b'a' + i; 4096];
+                GlusterFileExt::write_all_at(&*file_handle, &chunk, u64::from(i) * 4096).unwrap();
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    for i in 0..4u8 {
+        let mut chunk = [0; 4096];
+        GlusterFileExt::read_exact_at(&*file_handle, &mut chunk, u64::from(i) * 4096).unwrap();
+        assert!(chunk.iter().all(|b| *b == b'a' + i));
+    }
+
+    let written = GlusterFileExt::write_vectored_at(
+        &*file_handle,
+        &[IoSlice::new(b"hello "), IoSlice::new(b"world")],
+        0,
+    )
+    .unwrap();
+    assert_eq!(written, 11);
+    let mut first = [0; 6];
+    let mut second = [0; 5];
+    let read = GlusterFileExt::read_vectored_at(
+        &*file_handle,
+        &mut [IoSliceMut::new(&mut first), IoSliceMut::new(&mut second)],
+        0,
+    )
+    .unwrap();
+    assert_eq!(read, 11);
+    assert_eq!(&first, b"hello ");
+    assert_eq!(&second, b"world");
+
+    // With the trait in scope they are methods too
+    file_handle.write_all_at(b"HELLO", 0).unwrap();
+    let mut hello = [0; 5];
+    file_handle.read_exact_at(&mut hello, 0).unwrap();
+    assert_eq!(&hello, b"HELLO");
+    // A count past the end of the buffer is refused
+    assert!(file_handle.pwrite(b"hi", 4096, 0, 0).is_err());
+    drop(file_handle);
+
+    cluster.unlink(&path).unwrap();
+}
+
+#[test]
+fn vectored_io_test() {
+    use std::io::{IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
+
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let path = Path::new("/gfapi_vectored");
+    let mut file_handle = cluster
+        .create(&path, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+        .unwrap();
+    let written = file_handle
+        .writev(&[IoSlice::new(b"scatter "), IoSlice::new(b"gather")], 0)
+        .unwrap();
+    assert_eq!(written, 14);
+    let written = Write::write_vectored(
+        &mut file_handle,
+        &[IoSlice::new(b" and "), IoSlice::new(b"more")],
+    )
+    .unwrap();
+    assert_eq!(written, 9);
+
+    let mut first = [0; 8];
+    let mut second = [0; 6];
+    let read = file_handle
+        .preadv(
+            &mut [IoSliceMut::new(&mut first), IoSliceMut::new(&mut second)],
+            0,
+            0,
+        )
+        .unwrap();
+    assert_eq!(read, 14);
+    assert_eq!(&first, b"scatter ");
+    assert_eq!(&second, b"gather");
+
+    file_handle.seek(SeekFrom::Start(14)).unwrap();
+    let mut third = [0; 5];
+    let mut fourth = [0; 4];
+    let read = Read::read_vectored(
+        &mut file_handle,
+        &mut [IoSliceMut::new(&mut third), IoSliceMut::new(&mut fourth)],
+    )
+    .unwrap();
+    assert_eq!(read, 9);
+    assert_eq!(&third, b" and ");
+    assert_eq!(&fourth, b"more");
+    drop(file_handle);
+
+    cluster.unlink(&path).unwrap();
+}
+
+/// Run a future to completion on the current thread
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake};
+    use std::thread::{self, Thread};
+
+    struct ThreadWaker(Thread);
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let mut future = Box::pin(future);
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn async_io_test() {
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let path = Path::new("/gfapi_async");
+    let file_handle = cluster
+        .create(&path, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+        .unwrap();
+
+    let written = block_on(file_handle.pwrite_async(b"hello async".to_vec(), 0)).unwrap();
+    assert_eq!(written, 11);
+    block_on(file_handle.fsync_async()).unwrap();
+    let read = block_on(file_handle.pread_async(64, 6)).unwrap();
+    assert_eq!(read, b"async");
+
+    block_on(file_handle.zerofill_async(0, 5)).unwrap();
+    let read = block_on(file_handle.pread_async(5, 0)).unwrap();
+    assert_eq!(read, [0; 5]);
+    block_on(file_handle.ftruncate_async(5)).unwrap();
+    assert_eq!(file_handle.fstat().unwrap().st_size, 5);
+
+    // Dropping a future leaves the operation running with its buffer.
+    // Closing the file waits for it.
+    drop(file_handle.pwrite_async(vec![1; 4096], 0));
+    drop(file_handle);
+    assert_eq!(cluster.stat(&path).unwrap().st_size, 4096);
+
+    cluster.unlink(&path).unwrap();
+}
+
+#[test]
+fn async_gluster_test() {
+    use gfapi_sys::async_gluster::AsyncGluster;
+    use std::sync::Arc;
+
+    let cluster = Arc::new(Gluster::connect("test", "localhost", 24007).unwrap());
+    let async_cluster = AsyncGluster::new(cluster, 4).unwrap();
+    assert_eq!(async_cluster.workers(), 4);
+
+    let dir = Path::new("/gfapi_async_meta");
+    block_on(async_cluster.mkdir(&dir, S_IRWXU)).unwrap();
+    // Queue several operations before waiting on any of them
+    let creates: Vec<_> = (0..8)
+        .map(|i| {
+            async_cluster.create(
+                &dir.join(format!("file{}", i)),
+                O_CREAT | O_RDWR,
+                S_IRWXU,
+            )
+        })
+        .collect();
+    for create in creates {
+        drop(block_on(create).unwrap());
+    }
+    let entries = block_on(async_cluster.read_dir(&dir)).unwrap();
+    let files = entries
+        .iter()
+        .filter(|e| e.path.to_string_lossy().starts_with("file"))
+        .count();
+    assert_eq!(files, 8);
+
+    block_on(async_cluster.rename(&dir.join("file0"), &dir.join("renamed"))).unwrap();
+    assert!(block_on(async_cluster.exists(&dir.join("renamed"))).unwrap());
+    let stat = block_on(async_cluster.stat(&dir.join("renamed"))).unwrap();
+    assert_eq!(stat.st_size, 0);
+
+    block_on(async_cluster.remove_dir_all(&dir)).unwrap();
+    assert!(!block_on(async_cluster.exists(&dir)).unwrap());
+
+    // Operations queued before shutdown still run
+    let exists = async_cluster.exists(&dir);
+    async_cluster.shutdown();
+    assert!(!block_on(exists).unwrap());
+}
+
+#[test]
+// Operations queued beyond the limit fail instead of piling up
+fn async_gluster_queue_test() {
+    use gfapi_sys::async_gluster::AsyncGluster;
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+
+    let cluster = Arc::new(Gluster::connect("test", "localhost", 24007).unwrap());
+    let async_cluster = AsyncGluster::with_queue_limit(cluster, 1, 1).unwrap();
+    let (started, wait_started) = channel();
+    let (release, wait_release) = channel::<()>();
+    // Keep the only worker busy
+    let busy = async_cluster.run(move |_| {
+        started.send(()).unwrap();
+        wait_release.recv().unwrap();
+        Ok(())
+    });
+    wait_started.recv().unwrap();
+    let queued = async_cluster.exists(&Path::new("/"));
+    let rejected = async_cluster.exists(&Path::new("/"));
+    assert!(block_on(rejected).is_err());
+
+    release.send(()).unwrap();
+    block_on(busy).unwrap();
+    assert!(block_on(queued).unwrap());
+}
+
+#[test]
+fn copy_file_test() {
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let src = Path::new("/gfapi_copy_src");
+    let dst = Path::new("/gfapi_copy_dst");
+    let file_handle = cluster
+        .create(&src, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+        .unwrap();
+    let data: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    file_handle.pwrite(&data, data.len(), 0, 0).unwrap();
+    let times = [
+        timespec {
+            tv_sec: 1_000_000,
+            tv_nsec: 0,
+        },
+        timespec {
+            tv_sec: 2_000_000,
+            tv_nsec: 0,
+        },
+    ];
+    file_handle.futimens(&times).unwrap();
+    file_handle.fchmod(0o640).unwrap();
+    drop(file_handle);
+
+    assert_eq!(cluster.copy_file(&src, &dst).unwrap(), data.len() as u64);
+    let copy = cluster.open(&dst, O_RDWR).unwrap();
+    let mut contents = Vec::new();
+    copy.read_to_end(&mut contents).unwrap();
+    assert!(contents == data);
+    let stat = copy.fstat().unwrap();
+    assert_eq!(stat.st_mode & 0o7777, 0o640);
+    assert_eq!(stat.st_mtime, 2_000_000);
+
+    // Copying a range within one file
+    assert_eq!(copy.copy_range_to(&copy, 0, data.len() as u64, 4096).unwrap(), 4096);
+    drop(copy);
+
+    // Copying a file onto itself or a hard link of itself leaves it alone
+    let alias = Path::new("/gfapi_copy_alias");
+    cluster.link(&src, &alias).unwrap();
+    assert!(cluster.copy_file(&src, &src).is_err());
+    assert!(cluster.copy_file(&src, &alias).is_err());
+    assert_eq!(cluster.stat(&src).unwrap().st_size, data.len() as i64);
+    cluster.unlink(&alias).unwrap();
+
+    cluster.unlink(&src).unwrap();
+    cluster.unlink(&dst).unwrap();
+}
+
+#[test]
+fn extents_test() {
+    use gfapi_sys::sparse::ExtentKind;
+
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let path = Path::new("/gfapi_sparse");
+    let file_handle = cluster
+        .create(&path, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+        .unwrap();
+    let size = 8 * 1024 * 1024;
+    file_handle.ftruncate(size).unwrap();
+    file_handle.pwrite(&[1; 4096], 4096, 0, 0).unwrap();
+    file_handle.pwrite(&[2; 4096], 4096, 4 * 1024 * 1024, 0).unwrap();
+
+    let extents: Vec<_> = file_handle
+        .extents()
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    // Whether or not the volume reports holes the extents cover the file
+    // and the written ranges are data
+    let mut offset = 0;
+    for extent in &extents {
+        assert_eq!(extent.offset, offset);
+        assert!(extent.len > 0);
+        offset = extent.end();
+    }
+    assert_eq!(offset, size as u64);
+    for written in &[0, 4 * 1024 * 1024] {
+        let extent = extents
+            .iter()
+            .find(|e| e.offset <= *written && *written < e.end())
+            .unwrap();
+        assert_eq!(extent.kind, ExtentKind::Data);
+    }
+    drop(file_handle);
+
+    cluster.unlink(&path).unwrap();
+}
+
+#[test]
+fn transfer_test() {
+    use gfapi_sys::transfer::TransferOptions;
+    use std::fs;
+    use std::os::unix::fs::{FileExt, MetadataExt, PermissionsExt};
+
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let local_dir = std::env::temp_dir().join(format!("gfapi-transfer-{}", std::process::id()));
+    fs::create_dir_all(&local_dir).unwrap();
+    let local_src = local_dir.join("src");
+    let local_dst = local_dir.join("dst");
+    let remote = Path::new("/gfapi_transfer");
+
+    // A 16MiB file with data only at the start and the end
+    let size = 16 * 1024 * 1024;
+    let file = fs::File::create(&local_src).unwrap();
+    file.set_len(size).unwrap();
+    file.write_all_at(&[7; 4096], 0).unwrap();
+    file.write_all_at(&[9; 4096], size - 4096).unwrap();
+    // A data extent that is mostly zeros with one byte set every 64KiB
+    let mut mostly_zero = vec![0; 1024 * 1024];
+    for i in (0..mostly_zero.len()).step_by(64 * 1024) {
+        mostly_zero[i] = 1;
+    }
+    file.write_all_at(&mostly_zero, 4 * 1024 * 1024).unwrap();
+    file.set_permissions(fs::Permissions::from_mode(0o640)).unwrap();
+    drop(file);
+
+    let options = TransferOptions::new().mode(true).times(true);
+    assert_eq!(cluster.upload(&local_src, &remote, &options).unwrap(), size);
+    let stat = cluster.stat(&remote).unwrap();
+    assert_eq!(stat.st_size as u64, size);
+    assert_eq!(stat.st_mode & 0o7777, 0o640);
+    assert_eq!(stat.st_mtime, fs::metadata(&local_src).unwrap().mtime());
+
+    assert_eq!(
+        cluster.download(&remote, &local_dst, &options).unwrap(),
+        size
+    );
+    assert!(fs::read(&local_src).unwrap() == fs::read(&local_dst).unwrap());
+    let metadata = fs::metadata(&local_dst).unwrap();
+    assert_eq!(metadata.mode() & 0o7777, 0o640);
+    // Only the two data blocks and the 16 blocks holding a set byte
+    // should have been allocated
+    assert!(metadata.blocks() * 512 < 512 * 1024);
+
+    cluster.unlink(&remote).unwrap();
+    fs::remove_dir_all(&local_dir).unwrap();
+}
+
+#[test]
+// Subdirectories are checked before anything is sent to the server
+fn subdir_validation_test() {
+    let invalid = ["test/..", "test/.", "test/", "test/a/../b"];
+    for volume in invalid.iter() {
+        let result = GlusterBuilder::new(volume)
+            .server(VolfileServer::tcp("localhost", 24007))
+            .connect();
+        assert!(result.is_err(), "{} should be rejected", volume);
+    }
+    let result = GlusterBuilder::new("test")
+        .server(VolfileServer::tcp("localhost", 24007))
+        .subdir(Path::new("a/.."))
+        .connect();
+    assert!(result.is_err());
+}
+
+#[test]
+fn subdir_test() {
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    cluster.mkdir(&Path::new("/gfapi_subdir"), S_IRWXU).unwrap();
+    cluster
+        .mkdir(&Path::new("/gfapi_subdir/tenant"), S_IRWXU)
+        .unwrap();
+    cluster
+        .mkdir(&Path::new("/gfapi_subdir/other"), S_IRWXU)
+        .unwrap();
+    cluster
+        .create(
+            &Path::new("/gfapi_subdir/other/secret"),
+            O_CREAT | O_RDWR,
+            S_IRWXU,
+        )
+        .unwrap();
+
+    let tenant = GlusterBuilder::new("test/gfapi_subdir/tenant")
+        .server(VolfileServer::tcp("localhost", 24007))
+        .connect()
+        .unwrap();
+    assert_eq!(tenant.subdir(), Some(Path::new("/gfapi_subdir/tenant")));
+    assert_eq!(tenant.getcwd().unwrap(), "/");
+    tenant
+        .create(&Path::new("/file"), O_CREAT | O_RDWR, S_IRWXU)
+        .unwrap()
+        .write(b"tenant", 0)
+        .unwrap();
+    assert!(cluster
+        .exists(&Path::new("/gfapi_subdir/tenant/file"))
+        .unwrap());
+
+    // Relative paths and . resolve against the working directory
+    tenant.mkdir(&Path::new("dir"), S_IRWXU).unwrap();
+    tenant.chdir(&Path::new("dir")).unwrap();
+    assert_eq!(tenant.getcwd().unwrap(), "/dir");
+    assert!(tenant.exists(&Path::new("../file")).unwrap());
+    assert!(tenant.exists(&Path::new("./../file")).unwrap());
+    assert!(tenant.exists(&Path::new("/dir/../file")).unwrap());
+    tenant.chdir(&Path::new("/")).unwrap();
+
+    // .. may not climb out
+    assert!(tenant.stat(&Path::new("/../other/secret")).is_err());
+    assert!(tenant.stat(&Path::new("../other/secret")).is_err());
+    assert!(tenant.chdir(&Path::new("..")).is_err());
+
+    // Absolute symlinks resolve from the subdirectory
+    tenant.symlink(&Path::new("/"), &Path::new("esc")).unwrap();
+    assert!(tenant.exists(&Path::new("esc/file")).unwrap());
+    assert!(!tenant.exists(&Path::new("esc/other")).unwrap());
+    assert!(tenant.stat(&Path::new("esc/../other/secret")).is_err());
+    tenant
+        .symlink(&Path::new("/file"), &Path::new("dir/abs"))
+        .unwrap();
+    let mut buf = Vec::new();
+    tenant
+        .open(&Path::new("dir/abs"), O_RDWR)
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, b"tenant");
+    tenant
+        .symlink(&Path::new("../../other"), &Path::new("dir/up"))
+        .unwrap();
+    assert!(tenant.stat(&Path::new("dir/up/secret")).is_err());
+    // Calls that don't follow symlinks act on the link itself
+    assert_eq!(
+        tenant.lsstat(&Path::new("esc")).unwrap().st_mode & libc::S_IFMT,
+        libc::S_IFLNK
+    );
+    tenant.unlink(&Path::new("esc")).unwrap();
+    assert!(cluster.exists(&Path::new("/gfapi_subdir/tenant")).unwrap());
+
+    // Files outside the subdirectory can't be opened by gfid either
+    let secret = cluster
+        .gfid(&Path::new("/gfapi_subdir/other/secret"))
+        .unwrap();
+    assert!(tenant.open_by_gfid(&secret, O_RDWR).is_err());
+    assert!(tenant.gfid_to_path(&secret).is_err());
+
+    drop(tenant);
+    cluster.remove_dir_all(&Path::new("/gfapi_subdir")).unwrap();
+}
+
+#[test]
+fn subdir_object_test() {
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    cluster
+        .mkdir(&Path::new("/gfapi_subdir_objects"), S_IRWXU)
+        .unwrap();
+    cluster
+        .mkdir(&Path::new("/gfapi_subdir_objects/tenant"), S_IRWXU)
+        .unwrap();
+    cluster
+        .mkdir(&Path::new("/gfapi_subdir_objects/other"), S_IRWXU)
+        .unwrap();
+
+    let tenant = GlusterBuilder::new("test/gfapi_subdir_objects/tenant")
+        .server(VolfileServer::tcp("localhost", 24007))
+        .connect()
+        .unwrap();
+    let root = tenant.root_object().unwrap();
+    let dir = root.mkdir(&Path::new("dir"), S_IRWXU).unwrap();
+    assert!(root
+        .lookupat(&Path::new("/gfapi_subdir_objects/other"), true)
+        .is_err());
+    assert!(root.lookupat(&Path::new("../other"), true).is_err());
+    assert!(dir.lookupat(&Path::new("../../other"), true).is_err());
+    assert!(root.mkdir(&Path::new("/escaped"), S_IRWXU).is_err());
+    assert!(root.mkdir(&Path::new("dir/../x"), S_IRWXU).is_err());
+
+    // Symlinks resolve inside the subdirectory
+    root.symlink(&Path::new("esc"), &Path::new("/")).unwrap();
+    let found = root.lookupat(&Path::new("esc/dir"), true).unwrap();
+    assert_eq!(found.gfid().unwrap(), dir.gfid().unwrap());
+    assert!(root.lookupat(&Path::new("esc/other"), true).is_err());
+    let link = root.lookupat(&Path::new("esc"), false).unwrap();
+    assert_eq!(link.readlink().unwrap(), Path::new("/"));
+    assert_eq!(
+        dir.lookupat(&Path::new(".."), true)
+            .unwrap()
+            .gfid()
+            .unwrap(),
+        root.gfid().unwrap()
+    );
+
+    // GFIDs from outside the subdirectory are refused
+    let other = cluster
+        .gfid(&Path::new("/gfapi_subdir_objects/other"))
+        .unwrap();
+    assert!(tenant.object_from_gfid(&other).is_err());
+    let reopened = tenant.object_from_gfid(&dir.gfid().unwrap()).unwrap();
+    assert_eq!(reopened.gfid().unwrap(), dir.gfid().unwrap());
+
+    drop((link, found, dir, root, reopened));
+    drop(tenant);
+    cluster
+        .remove_dir_all(&Path::new("/gfapi_subdir_objects"))
+        .unwrap();
+}
+
+#[test]
+fn upcall_stream_test() {
+    use gfapi_sys::upcall::UpcallEvent;
+    use std::time::{Duration, Instant};
+
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let stream = cluster.upcall_stream().unwrap();
+    // Only one stream at a time
+    assert!(cluster.upcall_stream().is_err());
+    drop(stream);
+    let stream = cluster.upcall_stream().unwrap();
+
+    // A change made by another client invalidates the inode here.  The
+    // bricks only tell clients that have looked at it.
+    let path = Path::new("/gfapi_upcall");
+    cluster
+        .create(&path, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+        .unwrap();
+    let gfid = cluster.gfid(&path).unwrap();
+    cluster.stat(&path).unwrap();
+    let other = Gluster::connect("test", "localhost", 24007).unwrap();
+    other.chmod(&path, 0o600).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut invalidated = false;
+    while !invalidated && Instant::now() < deadline {
+        if let Some(Ok(UpcallEvent::InodeInvalidate { gfid: changed, .. })) =
+            stream.next_timeout(Duration::from_millis(100))
+        {
+            invalidated = changed == gfid;
+        }
+    }
+    assert!(invalidated);
+    drop(stream);
+
+    cluster.unlink(&path).unwrap();
+}
+
+#[test]
+fn credential_guard_test() {
+    use gfapi_sys::credentials::{self, CredentialGuard, Credentials};
+
+    let _cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let process = credentials::current();
+    let outer_creds = Credentials::new(1000, 1000).groups(&[10, 20]);
+    {
+        let _outer = CredentialGuard::new(&outer_creds).unwrap();
+        assert_eq!(credentials::current(), outer_creds);
+        {
+            let _inner = CredentialGuard::new(&Credentials::new(2000, 2000)).unwrap();
+            assert_eq!(credentials::current().uid, 2000);
+            assert!(credentials::current().groups.is_empty());
+        }
+        assert_eq!(credentials::current(), outer_creds);
+    }
+    // Back to the process identity, supplementary groups included
+    assert_eq!(credentials::current(), process);
+}
+
+#[test]
+fn errno_test() {
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let e = cluster.stat(&Path::new("/gfapi_missing")).unwrap_err();
+    assert!(e.is_errno(libc::ENOENT));
+    assert!(!e.is_errno(libc::ENOTCONN));
+    let e: std::io::Error = e.into();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+}
+
+#[test]
+// A volume with a subdirectory is one key however it is spelled
+fn volume_key_test() {
+    use gfapi_sys::registry::VolumeKey;
+    use std::path::PathBuf;
+
+    let servers = vec![VolfileServer::tcp("localhost", 24007)];
+    let key = VolumeKey::new(servers.clone(), "test/sub/dir");
+    assert_eq!(key.volume, "test");
+    assert_eq!(key.subdir, Some(PathBuf::from("/sub/dir")));
+    assert_eq!(
+        key,
+        VolumeKey::new(servers.clone(), "test").subdir(PathBuf::from("sub/dir/"))
+    );
+    assert_eq!(
+        key,
+        VolumeKey::new(servers, "test").subdir(PathBuf::from("/sub//dir"))
+    );
+}
+
+#[test]
+fn registry_test() {
+    use gfapi_sys::registry::{GlusterRegistry, VolumeKey};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let registry = GlusterRegistry::new(Duration::from_secs(0));
+    let key = VolumeKey::new(vec![VolfileServer::tcp("localhost", 24007)], "test");
+    let first = registry.get(&key).unwrap();
+    let second = registry.get(&key).unwrap();
+    assert!(Arc::ptr_eq(&first, &second));
+    assert_eq!(registry.len(), 1);
+
+    // Connections held outside the registry aren't reaped
+    assert_eq!(registry.reap_idle(), 0);
+    assert_eq!(registry.len(), 1);
+    drop((first, second));
+    assert_eq!(registry.reap_idle(), 1);
+    assert!(registry.is_empty());
+
+    // A fresh connection is opened after reaping
+    let third = registry.get(&key).unwrap();
+    assert!(third.exists(&Path::new("/")).unwrap());
+    registry.clear();
+    assert!(registry.is_empty());
+}
+
+#[test]
+// A get racing with clear either returns the connection from before the
+// clear or the one the registry holds afterwards, never an orphan
+fn registry_clear_race_test() {
+    use gfapi_sys::registry::{GlusterRegistry, VolumeKey};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    let registry = Arc::new(GlusterRegistry::new(Duration::from_secs(60)));
+    let key = VolumeKey::new(vec![VolfileServer::tcp("localhost", 24007)], "test");
+    let getters: Vec<_> = (0..4)
+        .map(|_| {
+            let registry = Arc::clone(&registry);
+            let key = key.clone();
+            thread::spawn(move || registry.get(&key).unwrap())
+        })
+        .collect();
+    // Give the getters time to find the slot while the first one connects
+    thread::sleep(Duration::from_millis(10));
+    registry.clear();
+    let results: Vec<_> = getters.into_iter().map(|g| g.join().unwrap()).collect();
+
+    let current = registry.get(&key).unwrap();
+    assert!(Arc::ptr_eq(&current, &registry.get(&key).unwrap()));
+    assert_eq!(registry.len(), 1);
+    // Anything connected after the clear is the connection the registry
+    // holds now.  At most one connection was made before it.
+    let mut others: Vec<_> = results
+        .iter()
+        .filter(|r| !Arc::ptr_eq(r, &current))
+        .map(Arc::as_ptr)
+        .collect();
+    others.sort();
+    others.dedup();
+    assert!(others.len() <= 1);
+}
+
+#[test]
+fn supervisor_test() {
+    use gfapi_sys::supervisor::{HealthState, SupervisedGluster};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    let states = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&states);
+    let supervised = SupervisedGluster::new(
+        GlusterBuilder::new("test").server(VolfileServer::tcp("localhost", 24007)),
+    )
+    .unwrap()
+    .failure_threshold(1)
+    .on_health_change(move |state| seen.lock().unwrap().push(state));
+    let first = supervised.get();
+
+    // Errors about the request don't touch the connection
+    let e = supervised
+        .run(|gluster| gluster.stat(&Path::new("/gfapi_missing")))
+        .unwrap_err();
+    assert!(e.is_errno(libc::ENOENT));
+    supervised.probe().unwrap();
+    assert!(Arc::ptr_eq(&first, &supervised.get()));
+    assert!(states.lock().unwrap().is_empty());
+
+    // ENOTCONN reconnects once and run still returns the error
+    let not_connected = || -> Result<(), GlusterError> {
+        Err(std::io::Error::from_raw_os_error(libc::ENOTCONN).into())
+    };
+    let e = supervised.run(|_| not_connected()).unwrap_err();
+    assert!(e.is_errno(libc::ENOTCONN));
+    assert!(!Arc::ptr_eq(&first, &supervised.get()));
+    assert_eq!(
+        *states.lock().unwrap(),
+        [
+            HealthState::Unhealthy,
+            HealthState::Reconnecting(1),
+            HealthState::Healthy
+        ]
+    );
+
+    // run_idempotent tries again on the new connection
+    let calls = AtomicUsize::new(0);
+    let value = supervised
+        .run_idempotent(|_| match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => not_connected().map(|_| 0),
+            n => Ok(n),
+        })
+        .unwrap();
+    assert_eq!(value, 1);
+}
+
+#[test]
+// Nothing listens on the first server so the volfile comes from the second
+fn volfile_server_failover_test() {
+    let cluster = GlusterBuilder::new("test")
+        .server(VolfileServer::tcp("localhost", 1))
+        .server(VolfileServer::tcp("localhost", 24007))
+        .connect()
+        .unwrap();
+    assert!(cluster.exists(&Path::new("/")).unwrap());
+
+    // Without the second server the connect fails
+    let result = GlusterBuilder::new("test")
+        .server(VolfileServer::tcp("localhost", 1))
+        .connect();
+    assert!(result.is_err());
+}
+
+#[test]
+// Volfile text is written to a private temporary file that is gone again
+// once the connect returns
+fn volfile_str_test() {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let volfile = cluster.get_volfile().unwrap();
+
+    let prefix = format!("gfapi-{}-", std::process::id());
+    let is_volfile = move |name: &str| name.starts_with(&prefix) && name.ends_with(".vol");
+    let done = Arc::new(AtomicBool::new(false));
+    // Look at the temporary file while the connect is using it
+    let watcher = {
+        let done = Arc::clone(&done);
+        let is_volfile = is_volfile.clone();
+        thread::spawn(move || {
+            let mut modes = Vec::new();
+            while !done.load(Ordering::SeqCst) {
+                for entry in fs::read_dir(std::env::temp_dir()).unwrap().flatten() {
+                    if !is_volfile(&entry.file_name().to_string_lossy()) {
+                        continue;
+                    }
+                    if let Ok(metadata) = entry.metadata() {
+                        modes.push(metadata.permissions().mode() & 0o7777);
+                    }
+                }
+            }
+            modes
+        })
+    };
+    let from_text = Gluster::from_volfile_str("test", &volfile).unwrap();
+    done.store(true, Ordering::SeqCst);
+    let modes = watcher.join().unwrap();
+    assert!(!modes.is_empty());
+    assert!(modes.iter().all(|mode| *mode == 0o600));
+    assert!(!fs::read_dir(std::env::temp_dir())
+        .unwrap()
+        .flatten()
+        .any(|entry| is_volfile(&entry.file_name().to_string_lossy())));
+
+    assert!(from_text.exists(&Path::new("/")).unwrap());
+}
+
+#[test]
+fn xlator_option_test() {
+    let cluster = GlusterBuilder::new("test")
+        .server(VolfileServer::tcp("localhost", 24007))
+        .xlator_option(XlatorOption::Performance(
+            PerformanceXlator::WriteBehind,
+            false,
+        ))
+        .raw_xlator_option("*-md-cache", "md-cache-timeout", "5")
+        .connect()
+        .unwrap();
+    assert!(cluster.exists(&Path::new("/")).unwrap());
+
+    // A value the translator can't parse fails glfs_init
+    let result = GlusterBuilder::new("test")
+        .server(VolfileServer::tcp("localhost", 24007))
+        .raw_xlator_option("*-md-cache", "md-cache-timeout", "not-a-number")
+        .connect();
+    assert!(result.is_err());
+    // And a name gluster can't take is refused before that
+    let result = GlusterBuilder::new("test")
+        .server(VolfileServer::tcp("localhost", 24007))
+        .raw_xlator_option("*-md-cache", "md-cache\0timeout", "5")
+        .connect();
+    assert!(result.is_err());
+}
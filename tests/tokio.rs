@@ -0,0 +1,62 @@
+#![cfg(feature = "tokio")]
+
+use std::io::SeekFrom;
+use std::path::Path;
+
+use gfapi_sys::gluster::*;
+use gfapi_sys::tokio_file::AsyncGlusterFile;
+use libc::{O_CREAT, O_RDWR, O_TRUNC, S_IRWXU};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_file_test() {
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let path = Path::new("/gfapi_tokio");
+    let file_handle = cluster
+        .create(&path, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+        .unwrap();
+    let mut file = AsyncGlusterFile::new(file_handle).unwrap();
+
+    file.write_all(b"hello ").await.unwrap();
+    file.write_all(b"tokio").await.unwrap();
+    file.flush().await.unwrap();
+    assert_eq!(file.position(), 11);
+
+    assert_eq!(file.seek(SeekFrom::Start(0)).await.unwrap(), 0);
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).await.unwrap();
+    assert_eq!(contents, "hello tokio");
+
+    assert_eq!(file.seek(SeekFrom::End(-5)).await.unwrap(), 6);
+    let mut word = [0; 5];
+    file.read_exact(&mut word).await.unwrap();
+    assert_eq!(&word, b"tokio");
+
+    let file_handle = file.into_inner().await.unwrap();
+    assert_eq!(file_handle.lseek(0, libc::SEEK_CUR).unwrap(), 11);
+    drop(file_handle);
+
+    cluster.unlink(&path).unwrap();
+}
+
+#[test]
+// A write still in flight when the file is dropped completes before the
+// fd is closed
+fn drop_in_flight_test() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let cluster = Gluster::connect("test", "localhost", 24007).unwrap();
+    let path = Path::new("/gfapi_tokio_drop");
+    let file_handle = cluster
+        .create(&path, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+        .unwrap();
+    let file = runtime.block_on(async {
+        let mut file = AsyncGlusterFile::new(file_handle).unwrap();
+        file.write_all(&[1; 4096]).await.unwrap();
+        file
+    });
+    // Outside of the runtime dropping waits for the write
+    drop(file);
+    assert_eq!(cluster.stat(&path).unwrap().st_size, 4096);
+
+    cluster.unlink(&path).unwrap();
+}
@@ -0,0 +1,134 @@
+use gfapi_sys::volfile::*;
+
+const DIST_REP: &str = include_str!("volfiles/dist-rep.vol");
+const DISPERSE: &str = include_str!("volfiles/disperse.vol");
+
+#[test]
+fn parse_distributed_replicate() {
+    let graph: VolumeGraph = DIST_REP.parse().unwrap();
+    assert_eq!(graph.xlators.len(), 10);
+    let top = graph.top().unwrap();
+    assert_eq!(top.name, "test");
+    assert_eq!(top.xlator_type, "debug/io-stats");
+    assert_eq!(top.subvolumes, vec!["test-md-cache".to_string()]);
+    // Quotes around values are removed
+    assert_eq!(
+        top.option("volume-id"),
+        Some("5a8b6f0e-3f0c-4a43-9b67-7d0a1cf7b4a8")
+    );
+
+    let bricks = graph.bricks();
+    assert_eq!(bricks.len(), 4);
+    assert_eq!(bricks[1].host, "server2");
+    assert_eq!(bricks[1].path, "/bricks/b1");
+
+    let replica_sets = graph.replica_sets();
+    assert_eq!(replica_sets.len(), 2);
+    assert_eq!(replica_sets[1][0].name, "test-client-2");
+    assert_eq!(replica_sets[1][1].name, "test-client-3");
+    assert!(graph.disperse_sets().is_empty());
+
+    let dht: Vec<&str> = graph
+        .dht_subvolumes()
+        .iter()
+        .map(|x| x.name.as_str())
+        .collect();
+    assert_eq!(dht, vec!["test-replicate-0", "test-replicate-1"]);
+}
+
+#[test]
+fn parse_disperse() {
+    let graph: VolumeGraph = DISPERSE.parse().unwrap();
+    let sets = graph.disperse_sets();
+    assert_eq!(sets.len(), 1);
+    assert_eq!(sets[0].len(), 3);
+    assert_eq!(sets[0][2].host, "node3");
+    assert!(graph.replica_sets().is_empty());
+    assert_eq!(graph.bricks_under("ec").len(), 3);
+}
+
+#[test]
+fn parse_errors() {
+    // Undefined subvolume
+    assert!(
+        "volume a\n type cluster/replicate\n subvolumes b\nend-volume\n"
+            .parse::<VolumeGraph>()
+            .is_err()
+    );
+    // Missing end-volume
+    assert!("volume a\n type protocol/client\n"
+        .parse::<VolumeGraph>()
+        .is_err());
+    // Missing type
+    assert!("volume a\nend-volume\n".parse::<VolumeGraph>().is_err());
+    // Duplicate volume
+    assert!(
+        "volume a\n type protocol/client\nend-volume\nvolume a\n type protocol/client\nend-volume\n"
+            .parse::<VolumeGraph>()
+            .is_err()
+    );
+    assert!("".parse::<VolumeGraph>().is_err());
+}
+
+#[test]
+fn round_trip() {
+    for volfile in &[DIST_REP, DISPERSE] {
+        let graph: VolumeGraph = volfile.parse().unwrap();
+        let written = graph.to_string();
+        let reparsed: VolumeGraph = written.parse().unwrap();
+        assert_eq!(graph, reparsed);
+        // Writing is stable once comments and spacing are normalized
+        assert_eq!(written, reparsed.to_string());
+    }
+}
+
+#[test]
+fn remove_write_behind() {
+    let mut graph: VolumeGraph = DIST_REP.parse().unwrap();
+    let removed = graph.remove_xlator("test-write-behind").unwrap();
+    assert_eq!(removed.xlator_type, "performance/write-behind");
+    assert_eq!(
+        graph.xlator("test-md-cache").unwrap().subvolumes,
+        vec!["test-dht".to_string()]
+    );
+    let reparsed: VolumeGraph = graph.to_string().parse().unwrap();
+    assert!(reparsed.xlator("test-write-behind").is_none());
+    assert_eq!(reparsed, graph);
+    assert!(graph.remove_xlator("test-write-behind").is_err());
+}
+
+#[test]
+fn insert_debug_xlator() {
+    let mut graph: VolumeGraph = DIST_REP.parse().unwrap();
+    let mut trace = Xlator::new("test-trace", "debug/trace");
+    trace.set_option("log-file", "yes");
+    graph.insert_above("test-dht", trace).unwrap();
+    assert_eq!(
+        graph.xlator("test-write-behind").unwrap().subvolumes,
+        vec!["test-trace".to_string()]
+    );
+    let reparsed: VolumeGraph = graph.to_string().parse().unwrap();
+    let trace = reparsed.xlator("test-trace").unwrap();
+    assert_eq!(trace.subvolumes, vec!["test-dht".to_string()]);
+    assert_eq!(trace.option("log-file"), Some("yes"));
+    assert_eq!(reparsed.bricks().len(), 4);
+    assert!(graph
+        .insert_above("test-dht", Xlator::new("test-trace", "debug/trace"))
+        .is_err());
+}
+
+#[test]
+fn quoted_values() {
+    let mut graph: VolumeGraph = DISPERSE.parse().unwrap();
+    graph
+        .xlator_mut("ec")
+        .unwrap()
+        .set_option("description", "two words");
+    let written = graph.to_string();
+    assert!(written.contains("option description \"two words\""));
+    let reparsed: VolumeGraph = written.parse().unwrap();
+    assert_eq!(
+        reparsed.xlator("ec").unwrap().option("description"),
+        Some("two words")
+    );
+}